@@ -1,10 +1,31 @@
 fn main() {
+    build_time();
+    // the unit tests run on the host, without the ESP32 linker scripts
+    if std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() != Ok("xtensa") {
+        return;
+    }
     linker_be_nice();
     // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
     //println!("cargo:rustc-link-arg=-Tcustom_memory.x");
     println!("cargo:rustc-link-arg=-Tlinkall.x");
 }
 
+/// Exposes the build time as `BUILD_UNIX_TIME`, the earliest time the
+/// firmware can be running at. `SOURCE_DATE_EPOCH` overrides it for
+/// reproducible builds.
+fn build_time() {
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        });
+    println!("cargo:rustc-env=BUILD_UNIX_TIME={secs}");
+}
+
 fn linker_be_nice() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 1 {
@@ -8,24 +8,30 @@
 
 extern crate alloc;
 use embassy_executor::Spawner;
-use embassy_time::{Duration, Timer, with_timeout};
+use embassy_time::{Duration, Timer};
 use esp_backtrace as _;
 use esp_hal::clock::CpuClock;
 use esp_hal::rtc_cntl::Rtc;
+use esp_hal::system::{CpuControl, Stack};
 use esp_hal::timer::timg::TimerGroup;
+use esp_hal_embassy::Executor;
 
 // use trouble_host::prelude::ExternalController;
-use log::{info, warn};
+use log::info;
+use once_cell::sync::Lazy;
+use static_cell::StaticCell;
 
 // This creates a default app-descriptor required by the esp-idf bootloader.
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
 esp_bootloader_esp_idf::esp_app_desc!();
 
+static mut APP_CORE_STACK: Stack<8192> = Stack::new();
+
 #[esp_hal_embassy::main]
 async fn main(spawner: Spawner) {
     // generator version: 0.5.0
 
-    esp_println::logger::init_logger_from_env();
+    esp_test::logger::init_logger_from_env();
 
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
     let peripherals = esp_hal::init(config);
@@ -45,9 +51,44 @@ async fn main(spawner: Spawner) {
         #[unsafe(link_section = ".dram2_uninit")] size: 96 * 1024 + 463
     );
 
+    // mounted before anything reads the config. Fails if the storage partition
+    // is missing or too small, rather than let littlefs write over the firmware
+    let fs =
+        esp_test::filesystem::mount().unwrap_or_else(|e| panic!("Cannot use the filesystem: {e}"));
+
     let timer0 = TimerGroup::new(peripherals.TIMG1);
     esp_hal_embassy::init(timer0.timer0);
 
+    // keeps the log across reboots, for what happens while nobody watches UART
+    spawner
+        .spawn(esp_test::filesystem::logfile::logfile_task(
+            log::LevelFilter::Info,
+            esp_test::filesystem::logfile::DEFAULT_ROTATION,
+        ))
+        .unwrap();
+
+    // loaded before the app core shares the filesystem, as loading the config
+    // does not wait for the filesystem to be free
+    Lazy::force(&esp_test::config::CONFIG);
+
+    // flash writes run on the app core. Every program and erase still stalls
+    // this core, Wi-Fi included, as it disables the flash cache, but for one
+    // sector erase at most instead of a whole littlefs operation
+    let mut cpu_control = CpuControl::new(peripherals.CPU_CTRL);
+    let _app_core = cpu_control
+        .start_app_core(
+            unsafe { &mut *core::ptr::addr_of_mut!(APP_CORE_STACK) },
+            move || {
+                static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+                EXECUTOR.init(Executor::new()).run(|spawner| {
+                    spawner
+                        .spawn(esp_test::filesystem::fs_worker_task(fs))
+                        .unwrap();
+                })
+            },
+        )
+        .unwrap();
+
     let rtc = Rtc::new(peripherals.LPWR);
     let rng = esp_hal::rng::Rng::new(peripherals.RNG);
 
@@ -62,52 +103,38 @@ async fn main(spawner: Spawner) {
         .await
         .unwrap();
 
-    // Wait for DHCP to assign an IP
+    // Wait for DHCP, SLAAC or the static config to assign an IP
     stack.wait_config_up().await;
-    if let Some(ip_config) = stack.config_v4() {
-        info!("DHCP assigned IP: {:?}", ip_config.address);
-    } else {
-        info!("No IP address assigned.");
+    info!("IP config: {}", esp_test::wifi::IpStatus::of(stack));
+
+    // shared with the time sync task, which may need HTTPS when NTP is blocked
+    let net_client_factory =
+        esp_test::net::init_client_factory(stack, peripherals.SHA, peripherals.RSA);
+
+    // setting time correct, and keeping it so in the background
+    esp_test::time::CLOCK.init(rtc);
+    spawner
+        .spawn(esp_test::time::sync::time_sync_task(
+            stack,
+            net_client_factory,
+        ))
+        .unwrap();
+    let sync_state = esp_test::time::sync::wait_initial_sync().await;
+    info!("Time sync: {sync_state:?}");
+    let tz = esp_test::config::CONFIG.lock().await.time.timezone();
+    if let Some(now) = esp_test::time::CLOCK.now_local(&tz) {
+        info!("Local time: {now} {}", now.abbreviation);
     }
 
-    // setting time correct
-    let current_time_us = loop {
-        match with_timeout(
-            Duration::from_secs(5),
-            esp_test::net::ntp::get_real_time_using_ntp(stack),
-        )
-        .await
-        {
-            Ok(r) => match r {
-                Ok(v) => {
-                    break v;
-                }
-                Err(e) => {
-                    warn!("Failed to get time from NTP due {e:?}");
-                    Timer::after(Duration::from_secs(5)).await;
-                }
-            },
-            Err(_) => {
-                warn!("Failed to get time from NTP due to timeout");
-                Timer::after(Duration::from_secs(5)).await;
-            }
-        }
-    };
-
-    rtc.set_current_time_us(current_time_us);
-
-    let net_client_factory = esp_test::net::NetClientFactory::<'_, 1, 1024, 1024>::new(
-        stack,
-        peripherals.SHA,
-        peripherals.RSA,
-    );
-    let tcp_client = net_client_factory.new_tcp_client();
-    let mut https_client = net_client_factory.new_https_client(&tcp_client);
+    static TCP_CLIENT: StaticCell<esp_test::net::SharedTcpClient> = StaticCell::new();
+    let tcp_client = TCP_CLIENT.init(net_client_factory.new_tcp_client());
 
     // HTTP GET to https://ifconfig.me/ip
     let mut res_buf = [0u8; 1024];
     loop {
         Timer::after(Duration::from_secs(5)).await;
+        // created per request so that CA certificate changes apply
+        let mut https_client = net_client_factory.new_https_client(tcp_client).await;
         let mut req = https_client
             .request(reqwless::request::Method::GET, "https://ifconfig.me/ip")
             .await
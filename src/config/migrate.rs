@@ -0,0 +1,75 @@
+use alloc::vec;
+use toml::{Table, Value};
+
+/// Schema version written by this firmware.
+pub const CONFIG_VERSION: u32 = 2;
+
+const VERSION_KEY: &str = "version";
+
+/// Upgrades a document by exactly one schema version.
+type Migration = fn(&mut Table) -> Result<(), MigrationError>;
+
+/// `MIGRATIONS[n]` upgrades a version `n` document to version `n + 1`.
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [v0_to_v1, v1_to_v2];
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum MigrationError {
+    #[error("Config version is not a non-negative integer")]
+    InvalidVersion,
+    #[error("Config version {0} is newer than supported version {CONFIG_VERSION}")]
+    UnsupportedVersion(u32),
+}
+
+/// Reads the schema version of a raw config document.
+///
+/// Documents written before versioning was introduced have no `version` key
+/// and are treated as version 0.
+pub fn version_of(doc: &Table) -> Result<u32, MigrationError> {
+    match doc.get(VERSION_KEY) {
+        None => Ok(0),
+        Some(Value::Integer(v)) => u32::try_from(*v).map_err(|_| MigrationError::InvalidVersion),
+        Some(_) => Err(MigrationError::InvalidVersion),
+    }
+}
+
+/// Upgrades `doc` in place to [`CONFIG_VERSION`].
+///
+/// Returns the version the document had before, or `None` if it was already
+/// current.
+pub fn migrate(doc: &mut Table) -> Result<Option<u32>, MigrationError> {
+    let from = version_of(doc)?;
+    if from > CONFIG_VERSION {
+        return Err(MigrationError::UnsupportedVersion(from));
+    }
+    if from == CONFIG_VERSION {
+        return Ok(None);
+    }
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        migration(doc)?;
+        doc.insert(VERSION_KEY.into(), Value::Integer(version as i64 + 1));
+    }
+    Ok(Some(from))
+}
+
+/// Version 1 introduced the `version` key itself; nothing else changed.
+fn v0_to_v1(_doc: &mut Table) -> Result<(), MigrationError> {
+    Ok(())
+}
+
+/// Version 2 replaced the single network in `[wifi]` by a list of
+/// `[[wifi.networks]]`.
+fn v1_to_v2(doc: &mut Table) -> Result<(), MigrationError> {
+    let Some(Value::Table(wifi)) = doc.get_mut("wifi") else {
+        return Ok(());
+    };
+    let mut network = Table::new();
+    for key in ["ssid", "password", "channel"] {
+        if let Some(value) = wifi.remove(key) {
+            network.insert(key.into(), value);
+        }
+    }
+    if !network.is_empty() {
+        wifi.insert("networks".into(), Value::Array(vec![Value::Table(network)]));
+    }
+    Ok(())
+}
@@ -0,0 +1,698 @@
+extern crate alloc;
+
+mod migrate;
+#[cfg(test)]
+mod power_loss;
+mod store;
+mod validate;
+
+pub use migrate::{CONFIG_VERSION, MigrationError};
+pub use store::{CONFIG_SUBSCRIBERS, ConfigChange, ConfigReceiver, ConfigStore};
+pub use validate::FieldError;
+
+use crate::filesystem::{
+    self, Origin, ReadError, Unavailable, read_or_backup, replace_file, restore_backup,
+};
+use crate::time::TimeZone;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::{Ipv4Addr, Ipv6Addr};
+use core::str::FromStr;
+use littlefs2::driver::Storage;
+use littlefs2::fs::{Allocation, FileType, Filesystem};
+use littlefs2::io::Error;
+use littlefs2::object_safe::DynFilesystem;
+use littlefs2::path::{Path, PathBuf};
+#[cfg(target_arch = "xtensa")]
+use log::error;
+use log::{info, warn};
+#[cfg(target_arch = "xtensa")]
+use once_cell::sync::Lazy;
+use serde::de::Unexpected;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use toml::Table;
+
+const CONFIG_PATH: &Path = littlefs2::path!("/config.toml");
+const CONFIG_MAX_SIZE: usize = 1024 * 4;
+/// Written to a filesystem without a config, from `config.toml` next to
+/// `Cargo.toml`, which holds the developer's Wi-Fi credentials and is not
+/// checked in.
+#[cfg(target_arch = "xtensa")]
+const EMBEDDED_CONFIG: &[u8] = include_bytes!("../../config.toml");
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Filesystem unavailable: {0}")]
+    Unavailable(Unavailable),
+    #[error("Failed to mount filesystem: {}", .0.code())]
+    Mount(Error),
+    #[error("Failed to format filesystem: {}", .0.code())]
+    Format(Error),
+    #[error("Failed to read config: {}", .0.code())]
+    Read(Error),
+    #[error("Failed to parse config: {0}")]
+    Parse(toml::de::Error),
+    #[error("Failed to migrate config: {0}")]
+    Migration(MigrationError),
+    #[error("Invalid config: {} field error(s)", .0.len())]
+    Invalid(Vec<FieldError>),
+    #[error("Failed to serialize config: {0}")]
+    Serialize(toml::ser::Error),
+    #[error("Failed to write back config: {}", .0.code())]
+    WriteBack(Error),
+}
+
+/// Where the active [`Config`] came from.
+#[derive(Debug, Clone, Default)]
+pub enum ConfigSource {
+    /// Parsed from `/config.toml` on flash.
+    #[default]
+    File,
+    /// Parsed from `/config.toml` after upgrading it from an older schema
+    /// version. The upgraded file has been written back.
+    Migrated { from: u32 },
+    /// Parsed from `/config.toml.bak`, because `/config.toml` failed with
+    /// this.
+    Backup(ConfigError),
+    /// The built-in default config.
+    Default,
+    /// The built-in default config, used because loading from flash failed.
+    Fallback(ConfigError),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Config {
+    pub version: u32,
+    pub wifi: Wifi,
+    pub net: Net,
+    #[serde(default)]
+    pub time: Time,
+    #[serde(skip)]
+    pub source: ConfigSource,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Wifi {
+    pub networks: Vec<Network>,
+}
+
+/// A Wi-Fi network to connect to. When several are in range, the one with
+/// the highest `priority` wins, ties are broken by signal strength.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Network {
+    pub ssid: String,
+    pub password: String,
+    pub channel: Option<u8>,
+    #[serde(default)]
+    pub priority: u8,
+    /// Restricts the network to a single access point.
+    pub bssid: Option<Bssid>,
+}
+
+/// A MAC address of an access point, written as `aa:bb:cc:dd:ee:ff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bssid(pub [u8; 6]);
+
+impl FromStr for Bssid {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bssid = [0u8; 6];
+        let mut octets = s.split(':');
+        for b in bssid.iter_mut() {
+            let octet = octets.next().ok_or("too few octets")?;
+            if octet.len() != 2 {
+                return Err("octets must be two hex digits");
+            }
+            *b = u8::from_str_radix(octet, 16).map_err(|_| "octets must be two hex digits")?;
+        }
+        match octets.next() {
+            None => Ok(Self(bssid)),
+            Some(_) => Err("too many octets"),
+        }
+    }
+}
+
+impl fmt::Display for Bssid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+impl<'de> Deserialize<'de> for Bssid {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Bssid {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Net {
+    #[serde(default)]
+    pub ipv4: Ipv4,
+    #[serde(default)]
+    pub ipv6: Ipv6,
+    #[serde(default)]
+    pub ntp: Ntp,
+    #[serde(default)]
+    pub http_time: HttpTime,
+    pub https: Https,
+}
+
+/// How the IPv4 address is obtained. Selected by the `mode` key of
+/// `[net.ipv4]`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Ipv4 {
+    Dhcp(Dhcp),
+    Static(StaticIpv4),
+}
+
+impl Default for Ipv4 {
+    fn default() -> Self {
+        Self::Dhcp(Dhcp::default())
+    }
+}
+
+/// DHCP client settings. Unset fields keep the embassy-net defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Dhcp {
+    /// Sent to the DHCP server as option 12.
+    pub hostname: Option<String>,
+    /// Caps the lease duration offered by the server.
+    pub max_lease_secs: Option<u64>,
+    pub discover_timeout_secs: Option<u64>,
+    /// The first REQUEST timeout. It doubles every two retries.
+    pub request_timeout_secs: Option<u64>,
+    pub request_retries: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StaticIpv4 {
+    pub address: Ipv4Addr,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4Addr>,
+    #[serde(default)]
+    pub dns_servers: Vec<Ipv4Addr>,
+}
+
+/// How the IPv6 address is obtained. Selected by the `mode` key of
+/// `[net.ipv6]`. IPv6 is off unless enabled here.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Ipv6 {
+    #[default]
+    Disabled,
+    /// Stateless autoconfiguration from router advertisements.
+    Slaac,
+    Static(StaticIpv6),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct StaticIpv6 {
+    pub address: Ipv6Addr,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv6Addr>,
+    #[serde(default)]
+    pub dns_servers: Vec<Ipv6Addr>,
+}
+
+/// Time synchronisation settings. Every field has a default, so the whole
+/// `[net.ntp]` section is optional.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Ntp {
+    /// Tried in order until one answers.
+    pub servers: Vec<String>,
+    pub port: u16,
+    /// How long to wait for each NTP request.
+    pub timeout_ms: u64,
+    /// Requests per server address. The one with the lowest round trip wins.
+    pub samples: u8,
+    /// Responses with a higher round trip are rejected.
+    pub max_roundtrip_ms: u64,
+    /// Failed sync rounds before giving up at boot. Retries forever if unset.
+    pub max_retries: Option<u32>,
+    /// How often to resync once the time is set.
+    pub resync_interval_secs: u64,
+}
+
+impl Default for Ntp {
+    fn default() -> Self {
+        Self {
+            servers: vec!["pool.ntp.org".to_string()],
+            port: 123,
+            timeout_ms: 2000,
+            samples: 4,
+            max_roundtrip_ms: 500,
+            max_retries: None,
+            resync_interval_secs: 60 * 60,
+        }
+    }
+}
+
+/// Fallback time source for networks that block NTP. The whole
+/// `[net.http_time]` section is optional.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HttpTime {
+    /// An `http://` or `https://` URL whose `Date` header is used when NTP
+    /// fails. The fallback is disabled if unset.
+    pub url: Option<String>,
+    /// How long to wait for the response, including the TLS handshake.
+    pub timeout_ms: u64,
+}
+
+impl Default for HttpTime {
+    fn default() -> Self {
+        Self {
+            url: None,
+            timeout_ms: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Https {
+    pub ca_cert: CaCert,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaCert {
+    pub pem: Vec<u8>,
+}
+
+impl<'de> Deserialize<'de> for CaCert {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        toml::map::Map::deserialize(deserializer).and_then(|map| {
+            let value = map.get_key_value("pem");
+            match value {
+                None => Err(serde::de::Error::missing_field("pem")),
+                Some((_k, v)) => match v.as_str() {
+                    None => Err(serde::de::Error::invalid_type(
+                        Unexpected::Other(v.type_str()),
+                        &"string",
+                    )),
+                    Some(s) => {
+                        let mut data = s.as_bytes().to_vec();
+                        data.push(0);
+                        Ok(CaCert { pem: data })
+                    }
+                },
+            }
+        })
+    }
+}
+
+impl Serialize for CaCert {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let pem = self.pem.strip_suffix(&[0]).unwrap_or(&self.pem);
+        let pem = core::str::from_utf8(pem).map_err(serde::ser::Error::custom)?;
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry("pem", pem)?;
+        map.end()
+    }
+}
+
+/// Local time settings. The whole `[time]` section is optional.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Time {
+    /// A POSIX TZ string, e.g. `CET-1CEST,M3.5.0,M10.5.0/3`.
+    pub tz: String,
+}
+
+impl Time {
+    /// The configured time zone, or UTC if `tz` does not parse, which
+    /// validation rules out.
+    pub fn timezone(&self) -> TimeZone {
+        self.tz.parse().unwrap_or_default()
+    }
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self {
+            tz: "UTC0".to_string(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            wifi: Wifi {
+                networks: vec![Network {
+                    ssid: "Wokwi-GUEST".to_string(),
+                    password: "".to_string(),
+                    channel: Some(6u8),
+                    priority: 0,
+                    bssid: None,
+                }],
+            },
+            net: Net {
+                ipv4: Ipv4::default(),
+                ipv6: Ipv6::default(),
+                ntp: Ntp::default(),
+                http_time: HttpTime::default(),
+                https: Https {
+                    ca_cert: CaCert {
+                        pem: crate::net::ca_certs::LETS_ENCRYPT_ISRG_ROOT_X1.to_vec(),
+                    },
+                },
+            },
+            time: Time::default(),
+            source: ConfigSource::Default,
+        }
+    }
+}
+
+impl Config {
+    fn fallback(reason: ConfigError) -> Self {
+        Self {
+            source: ConfigSource::Fallback(reason),
+            ..Self::default()
+        }
+    }
+}
+
+fn list(fs: &dyn DynFilesystem, path: &Path) -> Result<(), Error> {
+    fs.read_dir_and_then(path, &mut |iter| {
+        for entry in iter {
+            let entry = entry?;
+            match entry.file_type() {
+                FileType::File => info!("F {}", entry.path()),
+                FileType::Dir => match entry.file_name().as_str() {
+                    "." => (),
+                    ".." => (),
+                    _ => {
+                        info!("D {}", entry.path());
+                        list(fs, entry.path())?;
+                    }
+                },
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Replaces `/config.toml` without ever leaving a partially written file
+/// behind, keeping the previous one as `/config.toml.bak`.
+fn write_config(fs: &dyn DynFilesystem, contents: &[u8]) -> Result<(), Error> {
+    replace_file(fs, CONFIG_PATH, contents, true)
+}
+
+/// Persists `config` to `/config.toml` on the shared filesystem.
+async fn save(config: &Config) -> Result<(), ConfigError> {
+    let fs = filesystem::handle().map_err(ConfigError::Unavailable)?;
+    let config = config.clone();
+    fs.run(move |fs| save_fs(fs, &config)).await
+}
+
+/// Persists `config` to `/config.toml` on `storage`.
+pub fn save_to<S: Storage>(storage: &mut S, config: &Config) -> Result<(), ConfigError> {
+    let mut alloc = Allocation::new();
+    let fs = Filesystem::mount(&mut alloc, storage).map_err(ConfigError::Mount)?;
+    save_fs(&fs, config)
+}
+
+fn save_fs(fs: &dyn DynFilesystem, config: &Config) -> Result<(), ConfigError> {
+    let contents = toml::to_string(config).map_err(ConfigError::Serialize)?;
+    write_config(fs, contents.as_bytes()).map_err(ConfigError::WriteBack)
+}
+
+/// Parses a raw `/config.toml`, upgrading it to [`CONFIG_VERSION`] first.
+///
+/// If the document was migrated, the version it had before and the upgraded
+/// document are returned alongside the config.
+fn parse(data: &[u8]) -> Result<(Config, Option<(u32, Table)>), ConfigError> {
+    let mut doc: Table = toml::from_slice(data).map_err(ConfigError::Parse)?;
+    match migrate::migrate(&mut doc).map_err(ConfigError::Migration)? {
+        None => Ok((doc.try_into().map_err(ConfigError::Parse)?, None)),
+        Some(from) => {
+            let config = doc.clone().try_into().map_err(ConfigError::Parse)?;
+            Ok((config, Some((from, doc))))
+        }
+    }
+}
+
+/// Parses and validates a raw `/config.toml`.
+fn parse_valid(data: &[u8]) -> Result<(Config, Option<(u32, Table)>), ConfigError> {
+    let (config, migrated) = parse(data)?;
+    if let Err(errors) = config.validate() {
+        for e in &errors {
+            warn!("Invalid config: {e}");
+        }
+        return Err(ConfigError::Invalid(errors));
+    }
+    Ok((config, migrated))
+}
+
+/// Writes the config back if it had to be migrated, and records where it
+/// came from.
+fn finish_load(
+    fs: &dyn DynFilesystem,
+    mut config: Config,
+    migrated: Option<(u32, Table)>,
+    origin: Origin<ConfigError>,
+) -> Config {
+    if let Some((from, doc)) = migrated {
+        info!("Migrated config from version {from} to {CONFIG_VERSION}");
+        let written = toml::to_string(&doc)
+            .map_err(ConfigError::Serialize)
+            .and_then(|s| write_config(fs, s.as_bytes()).map_err(ConfigError::WriteBack));
+        if let Err(e) = written {
+            warn!("{e}");
+        }
+        config.source = ConfigSource::Migrated { from };
+    }
+    if let Origin::Backup(e) = origin {
+        let e = match e {
+            ReadError::Read(e) => ConfigError::Read(e),
+            ReadError::Parse(e) => e,
+        };
+        // a file from newer firmware is kept for when that firmware is back
+        let newer = matches!(
+            e,
+            ConfigError::Migration(MigrationError::UnsupportedVersion(_))
+        );
+        if !newer {
+            if let Err(e) = restore_backup(fs, CONFIG_PATH) {
+                warn!("Failed to restore config backup: {:?}", e.code());
+            }
+        }
+        config.source = ConfigSource::Backup(e);
+    }
+    config
+}
+
+/// Loads the config from `/config.toml` on the filesystem mounted by
+/// [`filesystem::mount`].
+///
+/// Recoverable problems fall back to [`Config::default`] and are recorded in
+/// [`Config::source`]: a missing config file is replaced by the embedded
+/// `config.toml` and an unparsable or invalid file is replaced by
+/// `/config.toml.bak`, or ignored if that does not work either. Files written
+/// by older firmware are upgraded to [`CONFIG_VERSION`] and written back,
+/// while files from newer firmware are left untouched.
+/// Only failures of these recovery steps are returned as errors.
+#[cfg(target_arch = "xtensa")]
+pub fn load() -> Result<Config, ConfigError> {
+    let fs = filesystem::handle().map_err(ConfigError::Unavailable)?;
+    fs.try_with(|fs| load_fs(fs, EMBEDDED_CONFIG))
+        .map_err(ConfigError::Unavailable)?
+}
+
+/// Like [`load`], but from the filesystem on `storage`, which is formatted
+/// if it does not mount, and with `default_config` instead of the embedded
+/// `config.toml` for a missing config file.
+pub fn load_from<S: Storage>(
+    storage: &mut S,
+    default_config: &[u8],
+) -> Result<Config, ConfigError> {
+    let mut alloc = Allocation::new();
+    let mut mount_error = None;
+    let mut format_error = None;
+    let fs = Filesystem::mount_or_else(&mut alloc, storage, |e, storage| {
+        warn!("Failed to mount filesystem: {:?}", e.code());
+        mount_error = Some(e);
+        Filesystem::format(storage).inspect_err(|&e| format_error = Some(e))
+    })
+    .map_err(|e| match format_error {
+        Some(e) => ConfigError::Format(e),
+        None => ConfigError::Mount(e),
+    })?;
+
+    if let Some(e) = mount_error {
+        warn!("No filesystem available. Formatted it and using default config");
+        return Ok(Config::fallback(ConfigError::Mount(e)));
+    }
+    load_fs(&fs, default_config)
+}
+
+fn load_fs(fs: &dyn DynFilesystem, default_config: &[u8]) -> Result<Config, ConfigError> {
+    match read_or_backup(fs, CONFIG_PATH, CONFIG_MAX_SIZE, parse_valid) {
+        Ok(((config, migrated), origin)) => Ok(finish_load(fs, config, migrated, origin)),
+        Err(ReadError::Read(e)) if e == Error::NO_SUCH_ENTRY => {
+            warn!("Failed to read config: {:?}", e.code());
+            if let Err(e) = list(fs, &PathBuf::new()) {
+                warn!("Failed to list filesystem: {:?}", e.code());
+            }
+            write_config(fs, default_config).map_err(ConfigError::WriteBack)?;
+            warn!("Using default config");
+            Ok(Config::fallback(ConfigError::Read(e)))
+        }
+        Err(ReadError::Read(e)) => Err(ConfigError::Read(e)),
+        Err(ReadError::Parse(e)) => {
+            warn!("{e}");
+            warn!("Using default config");
+            Ok(Config::fallback(e))
+        }
+    }
+}
+
+#[cfg(target_arch = "xtensa")]
+pub static CONFIG: Lazy<ConfigStore> = Lazy::new(|| {
+    ConfigStore::new(load().unwrap_or_else(|e| {
+        error!("{e}");
+        warn!("Using default config");
+        Config::fallback(e)
+    }))
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::ram_flash::{ERASED, RamFlash, blank_storage, formatted_storage};
+    use crate::filesystem::{AppStorage, FILESYSTEM_SIZE, geometry};
+
+    /// Stands in for the embedded `config.toml`.
+    fn default_toml() -> String {
+        toml::to_string(&Config::default()).unwrap()
+    }
+
+    fn read(storage: &mut AppStorage<RamFlash>, path: &Path) -> Result<Vec<u8>, Error> {
+        Filesystem::mount_and_then(storage, |fs| {
+            filesystem::read_file(fs, path, CONFIG_MAX_SIZE)
+        })
+    }
+
+    #[test]
+    fn blank_flash_is_formatted_and_gets_the_default_config() {
+        let mut storage = blank_storage();
+        let config = load_from(&mut storage, default_toml().as_bytes()).unwrap();
+        assert!(matches!(
+            config.source,
+            ConfigSource::Fallback(ConfigError::Mount(_))
+        ));
+
+        let config = load_from(&mut storage, default_toml().as_bytes()).unwrap();
+        assert!(matches!(
+            config.source,
+            ConfigSource::Fallback(ConfigError::Read(e)) if e == Error::NO_SUCH_ENTRY
+        ));
+        assert_eq!(
+            read(&mut storage, CONFIG_PATH).unwrap(),
+            default_toml().as_bytes()
+        );
+    }
+
+    #[test]
+    fn saved_config_is_loaded() {
+        let mut storage = formatted_storage();
+        let mut config = Config::default();
+        // the second save erases the blocks of the first
+        for tz in ["UTC0", "CET-1CEST,M3.5.0,M10.5.0/3"] {
+            config.time.tz = tz.to_string();
+            save_to(&mut storage, &config).unwrap();
+        }
+
+        let loaded = load_from(&mut storage, default_toml().as_bytes()).unwrap();
+        assert!(matches!(loaded.source, ConfigSource::File));
+        assert_eq!(loaded.time, config.time);
+        assert_eq!(loaded.wifi, config.wifi);
+        assert_eq!(loaded.net, config.net);
+        let backup = read(&mut storage, littlefs2::path!("/config.toml.bak")).unwrap();
+        assert!(core::str::from_utf8(&backup).unwrap().contains("UTC0"));
+    }
+
+    #[test]
+    fn unparsable_config_is_replaced_by_its_backup() {
+        let mut storage = formatted_storage();
+        let config = Config::default();
+        save_to(&mut storage, &config).unwrap();
+        save_to(&mut storage, &config).unwrap();
+        Filesystem::mount_and_then(&mut storage, |fs| fs.write(CONFIG_PATH, b"wifi = [")).unwrap();
+
+        let loaded = load_from(&mut storage, default_toml().as_bytes()).unwrap();
+        assert!(matches!(
+            loaded.source,
+            ConfigSource::Backup(ConfigError::Parse(_))
+        ));
+        assert_eq!(
+            read(&mut storage, CONFIG_PATH).unwrap(),
+            toml::to_string(&config).unwrap().as_bytes()
+        );
+    }
+
+    #[test]
+    fn config_from_newer_firmware_is_kept() {
+        let mut storage = formatted_storage();
+        Filesystem::mount_and_then(&mut storage, |fs| fs.write(CONFIG_PATH, b"version = 99"))
+            .unwrap();
+
+        let loaded = load_from(&mut storage, default_toml().as_bytes()).unwrap();
+        assert!(matches!(
+            loaded.source,
+            ConfigSource::Fallback(ConfigError::Migration(MigrationError::UnsupportedVersion(
+                99
+            )))
+        ));
+        assert_eq!(read(&mut storage, CONFIG_PATH).unwrap(), b"version = 99");
+    }
+
+    #[test]
+    fn write_to_unerased_flash_fails() {
+        let mut storage = formatted_storage();
+        save_to(&mut storage, &Config::default()).unwrap();
+        let flash = storage.into_inner();
+        let programmed = flash
+            .as_bytes()
+            .chunks(geometry::WRITE_SIZE)
+            .position(|word| word.iter().any(|&b| b != ERASED))
+            .unwrap()
+            * geometry::WRITE_SIZE;
+
+        // setting bits back to 1 takes an erase
+        let mut storage = AppStorage::new(flash.clone(), 0, FILESYSTEM_SIZE).unwrap();
+        assert_eq!(
+            storage.write(programmed, &[ERASED; geometry::WRITE_SIZE]),
+            Err(Error::IO)
+        );
+        assert_eq!(storage.into_inner(), flash);
+    }
+}
@@ -0,0 +1,217 @@
+//! Runs the config write-back paths through a power cut at every program and
+//! erase they do, on a [`RamFlash`].
+//!
+//! After each cut the flash is powered on again and must hold either the old
+//! or the new `/config.toml`, before and after [`load_from`] recovers it as
+//! the next boot would. The seed decides how much of the interrupted
+//! operation lands.
+
+use super::{
+    CONFIG_MAX_SIZE, CONFIG_PATH, Config, ConfigError, ConfigSource, load_from, migrate, save_to,
+};
+use crate::filesystem::power_loss::PowerLossFlash;
+use crate::filesystem::ram_flash::{RamFlash, formatted_storage};
+use crate::filesystem::{AppStorage, FILESYSTEM_SIZE, ReadError, read_or_backup};
+use alloc::string::{String, ToString};
+use core::convert::Infallible;
+use littlefs2::fs::Filesystem;
+use littlefs2::io::Error;
+use toml::{Table, Value};
+
+/// Seeds per scenario, each cutting the power at every operation once.
+const SEEDS: u32 = 1000;
+
+type CrashStorage = AppStorage<PowerLossFlash<RamFlash>>;
+
+/// What a campaign survived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Report {
+    /// Power cuts injected over all seeds.
+    crashes: u32,
+    /// Programs and erases of the longest uninterrupted run.
+    max_operations: u32,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{scenario}, seed {seed}, power cut at operation {cut}: {problem}")]
+struct Failure {
+    scenario: &'static str,
+    seed: u32,
+    cut: u32,
+    problem: Problem,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+enum Problem {
+    #[error("config cannot be read: {}", .0.code())]
+    Unreadable(Error),
+    #[error("config is neither the old nor the new contents: {0:?}")]
+    Mixed(String),
+    #[error("loading fell back to the default config: {0}")]
+    Fallback(ConfigError),
+    #[error("completed without the new contents")]
+    NotWritten,
+}
+
+/// [`save_to`] replaces the config by a different one.
+#[test]
+fn save_survives_power_loss() {
+    let old = to_toml(&Config::default());
+    let mut config = Config::default();
+    config.time.tz = "CET-1CEST,M3.5.0,M10.5.0/3".to_string();
+    config.net.ntp.servers = ["0.pool.ntp.org", "1.pool.ntp.org", "2.pool.ntp.org"]
+        .map(String::from)
+        .to_vec();
+    let new = to_toml(&config);
+
+    let report = run_campaign("save", &old, &new, |storage| {
+        let _ = save_to(storage, &config);
+    });
+    // every program and erase was interrupted once per seed
+    assert!(report.max_operations > 0);
+    assert_eq!(report.crashes, SEEDS * report.max_operations);
+}
+
+/// [`load_from`] upgrades a version 1 config and writes it back.
+#[test]
+fn migration_survives_power_loss() {
+    let outdated = version_1(&Config::default());
+    let mut doc: Table = toml::from_str(&outdated).unwrap();
+    assert_eq!(migrate::migrate(&mut doc).unwrap(), Some(1));
+    assert!(doc["wifi"].get("ssid").is_none());
+    let migrated = toml::to_string(&doc).unwrap();
+
+    let report = run_campaign("migrate", &outdated, &migrated, |storage| {
+        let _ = load_from(storage, to_toml(&Config::default()).as_bytes());
+    });
+    // every program and erase was interrupted once per seed
+    assert!(report.max_operations > 0);
+    assert_eq!(report.crashes, SEEDS * report.max_operations);
+}
+
+/// `config` as version 1 wrote it, with its single network in `[wifi]`
+/// rather than in `[[wifi.networks]]`.
+fn version_1(config: &Config) -> String {
+    let mut doc: Table = toml::from_str(&to_toml(config)).unwrap();
+    let network = &config.wifi.networks[0];
+    let mut wifi = Table::new();
+    wifi.insert("ssid".into(), network.ssid.clone().into());
+    wifi.insert("password".into(), network.password.clone().into());
+    if let Some(channel) = network.channel {
+        wifi.insert("channel".into(), Value::Integer(channel.into()));
+    }
+    doc.insert("version".into(), Value::Integer(1));
+    doc.insert("wifi".into(), Value::Table(wifi));
+    toml::to_string(&doc).unwrap()
+}
+
+/// Runs `op` on a flash holding `old` as the config, once per seed, and
+/// panics unless every power cut leaves `old` or `new` behind and `op` ends
+/// with `new` when it is not interrupted.
+fn run_campaign(
+    scenario: &'static str,
+    old: &str,
+    new: &str,
+    op: impl Fn(&mut CrashStorage),
+) -> Report {
+    let flash = flash_with(old);
+    let mut report = Report {
+        crashes: 0,
+        max_operations: 0,
+    };
+    for seed in 0..SEEDS {
+        run_scenario(scenario, seed, &flash, old, new, &op, &mut report)
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+    report
+}
+
+/// Cuts the power at the first, second, ... program or erase of `op` on a
+/// copy of `flash`, until `op` runs to the end.
+fn run_scenario(
+    scenario: &'static str,
+    seed: u32,
+    flash: &RamFlash,
+    old: &str,
+    new: &str,
+    op: impl Fn(&mut CrashStorage),
+    report: &mut Report,
+) -> Result<(), Failure> {
+    let mut cut = 0;
+    loop {
+        let corrupted = |problem| Failure {
+            scenario,
+            seed,
+            cut,
+            problem,
+        };
+        let mut flash = PowerLossFlash::new(flash.clone(), seed.wrapping_mul(0x9e37_79b9) ^ cut);
+        flash.cut_after(cut);
+        let mut storage = crash_storage(flash);
+        op(&mut storage);
+        let mut flash = storage.into_inner();
+
+        if flash.is_powered() {
+            report.max_operations = report.max_operations.max(flash.operations());
+            let mut storage = crash_storage(flash);
+            return match read_config(&mut storage).map_err(corrupted)? {
+                contents if contents == new => Ok(()),
+                _ => Err(corrupted(Problem::NotWritten)),
+            };
+        }
+        report.crashes += 1;
+        flash.power_on();
+        let mut storage = crash_storage(flash);
+        check_contents(&mut storage, old, new).map_err(corrupted)?;
+        match load_from(&mut storage, to_toml(&Config::default()).as_bytes()) {
+            Ok(Config {
+                source: ConfigSource::Fallback(e),
+                ..
+            })
+            | Err(e) => return Err(corrupted(Problem::Fallback(e))),
+            Ok(_) => (),
+        }
+        check_contents(&mut storage, old, new).map_err(corrupted)?;
+        cut += 1;
+    }
+}
+
+fn check_contents(storage: &mut CrashStorage, old: &str, new: &str) -> Result<(), Problem> {
+    match read_config(storage)? {
+        contents if contents == old || contents == new => Ok(()),
+        contents => Err(Problem::Mixed(contents)),
+    }
+}
+
+/// Reads the config as the next boot would, from the backup if
+/// `/config.toml` itself is missing.
+fn read_config(storage: &mut CrashStorage) -> Result<String, Problem> {
+    Filesystem::mount_and_then(storage, |fs| {
+        let read = read_or_backup(fs, CONFIG_PATH, CONFIG_MAX_SIZE, |data| {
+            Ok::<_, Infallible>(String::from_utf8_lossy(data).into_owned())
+        });
+        match read {
+            Ok((contents, _)) => Ok(contents),
+            Err(ReadError::Read(e)) => Err(e),
+        }
+    })
+    .map_err(Problem::Unreadable)
+}
+
+/// A freshly formatted flash holding `contents` as the config.
+fn flash_with(contents: &str) -> RamFlash {
+    let mut storage = formatted_storage();
+    Filesystem::mount_and_then(&mut storage, |fs| {
+        fs.write(CONFIG_PATH, contents.as_bytes())
+    })
+    .unwrap();
+    storage.into_inner()
+}
+
+fn crash_storage(flash: PowerLossFlash<RamFlash>) -> CrashStorage {
+    AppStorage::new(flash, 0, FILESYSTEM_SIZE).unwrap()
+}
+
+fn to_toml(config: &Config) -> String {
+    toml::to_string(config).unwrap()
+}
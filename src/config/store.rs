@@ -0,0 +1,102 @@
+use super::{Config, ConfigError, ConfigSource};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embassy_sync::watch::{Receiver, Watch};
+use log::info;
+
+/// Maximum number of tasks that can subscribe to config changes.
+pub const CONFIG_SUBSCRIBERS: usize = 4;
+
+pub type ConfigReceiver<'a> =
+    Receiver<'a, CriticalSectionRawMutex, ConfigChange, CONFIG_SUBSCRIBERS>;
+
+/// The sections touched by a [`ConfigStore::update`].
+///
+/// Subscribers only see the latest change, so they should compare the current
+/// config against what they applied instead of relying on these flags alone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConfigChange {
+    pub wifi: bool,
+    /// Only applied at startup, so it takes effect after a restart.
+    pub ipv4: bool,
+    /// Only applied at startup, so it takes effect after a restart.
+    pub ipv6: bool,
+    pub ntp: bool,
+    pub http_time: bool,
+    pub https: bool,
+    pub time: bool,
+}
+
+impl ConfigChange {
+    fn between(old: &Config, new: &Config) -> Self {
+        Self {
+            wifi: old.wifi != new.wifi,
+            ipv4: old.net.ipv4 != new.net.ipv4,
+            ipv6: old.net.ipv6 != new.net.ipv6,
+            ntp: old.net.ntp != new.net.ntp,
+            http_time: old.net.http_time != new.net.http_time,
+            https: old.net.https != new.net.https,
+            time: old.time != new.time,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The live config, which can be changed at runtime and persisted to
+/// `/config.toml`.
+pub struct ConfigStore {
+    config: Mutex<CriticalSectionRawMutex, Config>,
+    changes: Watch<CriticalSectionRawMutex, ConfigChange, CONFIG_SUBSCRIBERS>,
+}
+
+impl ConfigStore {
+    pub const fn new(config: Config) -> Self {
+        Self {
+            config: Mutex::new(config),
+            changes: Watch::new(),
+        }
+    }
+
+    /// Locks the current config for reading.
+    ///
+    /// Keep the guard short-lived, as it blocks [`ConfigStore::update`].
+    pub async fn lock(&self) -> MutexGuard<'_, CriticalSectionRawMutex, Config> {
+        self.config.lock().await
+    }
+
+    /// Returns a receiver that is notified after every successful update, or
+    /// `None` if all [`CONFIG_SUBSCRIBERS`] slots are taken.
+    pub fn subscribe(&self) -> Option<ConfigReceiver<'_>> {
+        self.changes.receiver()
+    }
+
+    /// Applies `f` to a copy of the current config, validates the result and
+    /// persists it to `/config.toml` before making it current.
+    ///
+    /// On error the current config is left unchanged.
+    pub async fn update<F>(&self, f: F) -> Result<ConfigChange, ConfigError>
+    where
+        F: FnOnce(&mut Config),
+    {
+        let mut current = self.config.lock().await;
+        let mut updated = current.clone();
+        f(&mut updated);
+        updated.validate().map_err(ConfigError::Invalid)?;
+
+        let change = ConfigChange::between(&current, &updated);
+        if change.is_empty() {
+            return Ok(change);
+        }
+        super::save(&updated).await?;
+        updated.source = ConfigSource::File;
+        *current = updated;
+        drop(current);
+
+        info!("Config updated: {change:?}");
+        self.changes.sender().send(change);
+        Ok(change)
+    }
+}
@@ -0,0 +1,650 @@
+use super::{
+    CaCert, Config, Dhcp, HttpTime, Https, Ipv4, Ipv6, Net, Network, Ntp, StaticIpv4, StaticIpv6,
+    Time, Wifi,
+};
+use crate::time::TimeZone;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+const SSID_MAX_LEN: usize = 32;
+const PASSWORD_MIN_LEN: usize = 8;
+const PASSWORD_MAX_LEN: usize = 63;
+const CHANNEL_MIN: u8 = 1;
+const CHANNEL_MAX: u8 = 14;
+const HOSTNAME_MAX_LEN: usize = 32;
+const PREFIX_LEN_MAX: u8 = 32;
+const PREFIX_LEN_MAX_V6: u8 = 128;
+const DNS_SERVERS_MAX: usize = 3;
+const DOMAIN_NAME_MAX_LEN: usize = 253;
+/// SNTP clients must not poll more often than this (RFC 4330).
+const RESYNC_INTERVAL_MIN_SECS: u64 = 16;
+const NTP_SAMPLES_MAX: u8 = 8;
+const URL_MAX_LEN: usize = 256;
+
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+/// A semantic error in a single config field, e.g. `wifi.networks[0].channel`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub path: String,
+    pub reason: &'static str,
+}
+
+impl FieldError {
+    fn new(path: &str, reason: &'static str) -> Self {
+        Self {
+            path: path.to_string(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.reason)
+    }
+}
+
+impl Config {
+    /// Checks the values serde cannot, so that a bad config is caught at load
+    /// time instead of deep inside the Wi-Fi or TLS stack.
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        self.wifi.validate("wifi", &mut errors);
+        self.net.validate("net", &mut errors);
+        self.time.validate("time", &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl Wifi {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        let networks = field(path, "networks");
+        if self.networks.is_empty() {
+            errors.push(FieldError::new(&networks, "must list at least one network"));
+        }
+        for (i, network) in self.networks.iter().enumerate() {
+            network.validate(&format!("{networks}[{i}]"), errors);
+        }
+    }
+}
+
+impl Network {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        if self.ssid.is_empty() {
+            errors.push(FieldError::new(&field(path, "ssid"), "must not be empty"));
+        } else if self.ssid.len() > SSID_MAX_LEN {
+            errors.push(FieldError::new(
+                &field(path, "ssid"),
+                "must be at most 32 bytes",
+            ));
+        }
+
+        let password_len = self.password.chars().count();
+        if password_len != 0 && !(PASSWORD_MIN_LEN..=PASSWORD_MAX_LEN).contains(&password_len) {
+            errors.push(FieldError::new(
+                &field(path, "password"),
+                "must be empty or 8 to 63 characters",
+            ));
+        }
+
+        let channels = CHANNEL_MIN..=CHANNEL_MAX;
+        if self.channel.is_some_and(|c| !channels.contains(&c)) {
+            errors.push(FieldError::new(
+                &field(path, "channel"),
+                "must be between 1 and 14",
+            ));
+        }
+    }
+}
+
+impl Net {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        self.ipv4.validate(&field(path, "ipv4"), errors);
+        self.ipv6.validate(&field(path, "ipv6"), errors);
+        self.ntp.validate(&field(path, "ntp"), errors);
+        self.http_time.validate(&field(path, "http_time"), errors);
+        self.https.validate(&field(path, "https"), errors);
+    }
+}
+
+impl Ipv4 {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        match self {
+            Ipv4::Dhcp(dhcp) => dhcp.validate(path, errors),
+            Ipv4::Static(ipv4) => ipv4.validate(path, errors),
+        }
+    }
+}
+
+impl Dhcp {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        if let Some(hostname) = &self.hostname {
+            let valid_chars = hostname
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+            if hostname.is_empty() || hostname.len() > HOSTNAME_MAX_LEN {
+                errors.push(FieldError::new(
+                    &field(path, "hostname"),
+                    "must be 1 to 32 bytes",
+                ));
+            } else if !valid_chars || hostname.starts_with('-') || hostname.ends_with('-') {
+                errors.push(FieldError::new(
+                    &field(path, "hostname"),
+                    "must only contain letters, digits and inner hyphens",
+                ));
+            }
+        }
+
+        let timeouts = [
+            ("max_lease_secs", self.max_lease_secs),
+            ("discover_timeout_secs", self.discover_timeout_secs),
+            ("request_timeout_secs", self.request_timeout_secs),
+        ];
+        for (name, secs) in timeouts {
+            if secs == Some(0) {
+                errors.push(FieldError::new(&field(path, name), "must not be zero"));
+            }
+        }
+    }
+}
+
+impl StaticIpv4 {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        if self.address.is_unspecified() || self.address.is_broadcast() {
+            errors.push(FieldError::new(
+                &field(path, "address"),
+                "must be a host address",
+            ));
+        }
+        if self.prefix_len > PREFIX_LEN_MAX {
+            errors.push(FieldError::new(
+                &field(path, "prefix_len"),
+                "must be at most 32",
+            ));
+        } else if let Some(gateway) = self.gateway {
+            let mask = u32::MAX
+                .checked_shl(32 - self.prefix_len as u32)
+                .unwrap_or(0);
+            if u32::from(gateway) & mask != u32::from(self.address) & mask {
+                errors.push(FieldError::new(
+                    &field(path, "gateway"),
+                    "must be in the same subnet as address",
+                ));
+            }
+        }
+        if self.dns_servers.len() > DNS_SERVERS_MAX {
+            errors.push(FieldError::new(
+                &field(path, "dns_servers"),
+                "must list at most 3 servers",
+            ));
+        }
+    }
+}
+
+impl Ipv6 {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        if let Ipv6::Static(ipv6) = self {
+            ipv6.validate(path, errors);
+        }
+    }
+}
+
+impl StaticIpv6 {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        if self.address.is_unspecified() || self.address.is_multicast() {
+            errors.push(FieldError::new(
+                &field(path, "address"),
+                "must be a unicast address",
+            ));
+        }
+        if self.prefix_len > PREFIX_LEN_MAX_V6 {
+            errors.push(FieldError::new(
+                &field(path, "prefix_len"),
+                "must be at most 128",
+            ));
+        }
+        if self
+            .gateway
+            .is_some_and(|g| g.is_unspecified() || g.is_multicast())
+        {
+            errors.push(FieldError::new(
+                &field(path, "gateway"),
+                "must be a unicast address",
+            ));
+        }
+        if self.dns_servers.len() > DNS_SERVERS_MAX {
+            errors.push(FieldError::new(
+                &field(path, "dns_servers"),
+                "must list at most 3 servers",
+            ));
+        }
+    }
+}
+
+impl Ntp {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        let servers = field(path, "servers");
+        if self.servers.is_empty() {
+            errors.push(FieldError::new(&servers, "must list at least one server"));
+        }
+        for (i, server) in self.servers.iter().enumerate() {
+            if server.is_empty() || server.len() > DOMAIN_NAME_MAX_LEN {
+                errors.push(FieldError::new(
+                    &format!("{servers}[{i}]"),
+                    "must be 1 to 253 bytes",
+                ));
+            }
+        }
+        if self.port == 0 {
+            errors.push(FieldError::new(&field(path, "port"), "must not be zero"));
+        }
+        if self.timeout_ms == 0 {
+            errors.push(FieldError::new(
+                &field(path, "timeout_ms"),
+                "must not be zero",
+            ));
+        }
+        if !(1..=NTP_SAMPLES_MAX).contains(&self.samples) {
+            errors.push(FieldError::new(
+                &field(path, "samples"),
+                "must be between 1 and 8",
+            ));
+        }
+        if self.max_roundtrip_ms == 0 {
+            errors.push(FieldError::new(
+                &field(path, "max_roundtrip_ms"),
+                "must not be zero",
+            ));
+        }
+        if self.resync_interval_secs < RESYNC_INTERVAL_MIN_SECS {
+            errors.push(FieldError::new(
+                &field(path, "resync_interval_secs"),
+                "must be at least 16",
+            ));
+        }
+    }
+}
+
+impl HttpTime {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        if let Some(url) = &self.url {
+            let host = url
+                .strip_prefix("https://")
+                .or_else(|| url.strip_prefix("http://"))
+                .map(|rest| rest.split('/').next().unwrap_or(rest));
+            if host.is_none_or(str::is_empty) {
+                errors.push(FieldError::new(
+                    &field(path, "url"),
+                    "must be an http:// or https:// URL with a host",
+                ));
+            } else if url.len() > URL_MAX_LEN {
+                errors.push(FieldError::new(
+                    &field(path, "url"),
+                    "must be at most 256 bytes",
+                ));
+            }
+        }
+        if self.timeout_ms == 0 {
+            errors.push(FieldError::new(
+                &field(path, "timeout_ms"),
+                "must not be zero",
+            ));
+        }
+    }
+}
+
+impl Https {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        self.ca_cert.validate(&field(path, "ca_cert"), errors);
+    }
+}
+
+impl CaCert {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        if let Err(reason) = check_pem(&self.pem) {
+            errors.push(FieldError::new(&field(path, "pem"), reason));
+        }
+    }
+}
+
+impl Time {
+    fn validate(&self, path: &str, errors: &mut Vec<FieldError>) {
+        if self.tz.parse::<TimeZone>().is_err() {
+            errors.push(FieldError::new(
+                &field(path, "tz"),
+                "is not a valid POSIX TZ string",
+            ));
+        }
+    }
+}
+
+fn field(parent: &str, name: &str) -> String {
+    format!("{parent}.{name}")
+}
+
+/// Checks that `pem` holds one or more certificates whose base64 bodies
+/// decode to the outer structure of an X.509 certificate: a DER `SEQUENCE`
+/// of the signed `SEQUENCE`, the signature algorithm `SEQUENCE` and the
+/// signature `BIT STRING`.
+///
+/// The fields inside are not parsed, as the mbedtls X.509 parser is not
+/// available here. A certificate that mbedtls rejects, e.g. for a key type
+/// it does not support, passes and only fails the first TLS handshake.
+fn check_pem(pem: &[u8]) -> Result<(), &'static str> {
+    let pem = pem.strip_suffix(&[0]).unwrap_or(pem);
+    let mut rest = core::str::from_utf8(pem).map_err(|_| "is not valid UTF-8")?;
+    let mut certificates = 0;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        rest = rest
+            .strip_prefix(PEM_BEGIN)
+            .ok_or("missing BEGIN CERTIFICATE line")?;
+        let end = rest.find(PEM_END).ok_or("missing END CERTIFICATE line")?;
+        let der = decode_base64(&rest[..end]).ok_or("contains invalid base64")?;
+        check_certificate(&der)?;
+        rest = &rest[end + PEM_END.len()..];
+        certificates += 1;
+    }
+    if certificates == 0 {
+        return Err("contains no certificate");
+    }
+    Ok(())
+}
+
+fn check_certificate(der: &[u8]) -> Result<(), &'static str> {
+    const SEQUENCE: u8 = 0x30;
+    const BIT_STRING: u8 = 0x03;
+    const NOT_X509: &str = "does not have the structure of an X.509 certificate (its fields \
+        are not checked)";
+
+    let (SEQUENCE, certificate, []) = der_element(der)? else {
+        return Err("is not a DER certificate");
+    };
+    let (SEQUENCE, _, rest) = der_element(certificate).map_err(|_| NOT_X509)? else {
+        return Err(NOT_X509);
+    };
+    let (SEQUENCE, _, rest) = der_element(rest).map_err(|_| NOT_X509)? else {
+        return Err(NOT_X509);
+    };
+    let (BIT_STRING, _, []) = der_element(rest).map_err(|_| NOT_X509)? else {
+        return Err(NOT_X509);
+    };
+    Ok(())
+}
+
+/// Splits the first DER element off `der`, as its tag, its contents and
+/// the rest of `der`.
+fn der_element(der: &[u8]) -> Result<(u8, &[u8], &[u8]), &'static str> {
+    let (tag, len, rest) = match der {
+        [tag, len, rest @ ..] if len & 0x80 == 0 => (*tag, *len as usize, rest),
+        [tag, len, rest @ ..] => {
+            let octets = (len & 0x7f) as usize;
+            if octets == 0 || octets > 4 || rest.len() < octets {
+                return Err("has a malformed certificate length");
+            }
+            let (len, rest) = rest.split_at(octets);
+            let len = len.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize);
+            (*tag, len, rest)
+        }
+        _ => return Err("has a truncated certificate"),
+    };
+    if rest.len() < len {
+        return Err("has a truncated certificate");
+    }
+    let (contents, rest) = rest.split_at(len);
+    Ok((tag, contents, rest))
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut padding = 0;
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        if c == b'=' {
+            padding += 1;
+            continue;
+        }
+        if padding > 0 {
+            return None;
+        }
+        acc = (acc << 6) | value(c)?;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    (padding <= 2).then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ca_certs::LETS_ENCRYPT_ISRG_ROOT_X1;
+    use core::net::{Ipv4Addr, Ipv6Addr};
+
+    type Edit = fn(&mut Config);
+
+    /// The paths of the errors in the default config after `edit`.
+    fn error_paths(edit: Edit) -> Vec<String> {
+        let mut config = Config::default();
+        edit(&mut config);
+        match config.validate() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.into_iter().map(|e| e.path).collect(),
+        }
+    }
+
+    fn network(config: &mut Config) -> &mut Network {
+        &mut config.wifi.networks[0]
+    }
+
+    fn static_ipv4(prefix_len: u8, dns_servers: usize) -> Ipv4 {
+        Ipv4::Static(StaticIpv4 {
+            address: Ipv4Addr::new(192, 168, 1, 2),
+            prefix_len,
+            gateway: None,
+            dns_servers: alloc::vec![Ipv4Addr::new(192, 168, 1, 1); dns_servers],
+        })
+    }
+
+    fn static_ipv6(prefix_len: u8, dns_servers: usize) -> Ipv6 {
+        let address = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2);
+        Ipv6::Static(StaticIpv6 {
+            address,
+            prefix_len,
+            gateway: None,
+            dns_servers: alloc::vec![address; dns_servers],
+        })
+    }
+
+    #[test]
+    fn the_default_config_is_valid() {
+        assert_eq!(Config::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn values_at_the_limits_pass() {
+        let cases: [Edit; 13] = [
+            |c| network(c).ssid = "s".repeat(32),
+            // multi-byte characters count as their bytes
+            |c| network(c).ssid = "é".repeat(16),
+            |c| network(c).password = "p".repeat(8),
+            // and here as one character each
+            |c| network(c).password = "é".repeat(8),
+            |c| network(c).password = "p".repeat(63),
+            |c| network(c).channel = Some(1),
+            |c| network(c).channel = Some(14),
+            |c| network(c).channel = None,
+            |c| c.net.ipv4 = static_ipv4(32, 3),
+            |c| c.net.ipv4 = static_ipv4(0, 0),
+            |c| c.net.ipv6 = static_ipv6(128, 3),
+            |c| c.net.ntp.resync_interval_secs = 16,
+            |c| c.net.ntp.samples = 8,
+        ];
+        for (i, edit) in cases.into_iter().enumerate() {
+            assert_eq!(error_paths(edit), Vec::<String>::new(), "case {i}");
+        }
+    }
+
+    #[test]
+    fn values_past_the_limits_fail_with_their_path() {
+        let cases: [(Edit, &str); 17] = [
+            (
+                |c| network(c).ssid = "s".repeat(33),
+                "wifi.networks[0].ssid",
+            ),
+            (
+                |c| network(c).ssid = "é".repeat(17),
+                "wifi.networks[0].ssid",
+            ),
+            (|c| network(c).ssid.clear(), "wifi.networks[0].ssid"),
+            (
+                |c| network(c).password = "p".into(),
+                "wifi.networks[0].password",
+            ),
+            (
+                |c| network(c).password = "p".repeat(7),
+                "wifi.networks[0].password",
+            ),
+            (
+                |c| network(c).password = "é".repeat(7),
+                "wifi.networks[0].password",
+            ),
+            (
+                |c| network(c).password = "p".repeat(64),
+                "wifi.networks[0].password",
+            ),
+            (|c| network(c).channel = Some(0), "wifi.networks[0].channel"),
+            (
+                |c| network(c).channel = Some(15),
+                "wifi.networks[0].channel",
+            ),
+            (
+                |c| {
+                    let mut second = c.wifi.networks[0].clone();
+                    second.channel = Some(15);
+                    c.wifi.networks.push(second);
+                },
+                "wifi.networks[1].channel",
+            ),
+            (|c| c.wifi.networks.clear(), "wifi.networks"),
+            (|c| c.net.ipv4 = static_ipv4(33, 3), "net.ipv4.prefix_len"),
+            (|c| c.net.ipv4 = static_ipv4(32, 4), "net.ipv4.dns_servers"),
+            (|c| c.net.ipv6 = static_ipv6(129, 3), "net.ipv6.prefix_len"),
+            (|c| c.net.ipv6 = static_ipv6(128, 4), "net.ipv6.dns_servers"),
+            (
+                |c| c.net.ntp.resync_interval_secs = 15,
+                "net.ntp.resync_interval_secs",
+            ),
+            (|c| c.net.ntp.samples = 9, "net.ntp.samples"),
+        ];
+        for (i, (edit, path)) in cases.into_iter().enumerate() {
+            assert_eq!(error_paths(edit), [path], "case {i}");
+        }
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        assert_eq!(
+            error_paths(|c| {
+                network(c).channel = Some(0);
+                c.net.ntp.resync_interval_secs = 0;
+                c.time.tz = "".into();
+            }),
+            [
+                "wifi.networks[0].channel",
+                "net.ntp.resync_interval_secs",
+                "time.tz"
+            ]
+        );
+    }
+
+    fn pem(der: &[u8]) -> String {
+        const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut body = String::new();
+        for chunk in der.chunks(3) {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    body.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+                } else {
+                    body.push('=');
+                }
+            }
+        }
+        format!("{PEM_BEGIN}\n{body}\n{PEM_END}\n")
+    }
+
+    #[test]
+    fn certificates_pass() {
+        assert_eq!(check_pem(LETS_ENCRYPT_ISRG_ROOT_X1), Ok(()));
+        // as mbedtls needs it, with a single NUL at the end
+        let pem = LETS_ENCRYPT_ISRG_ROOT_X1.strip_suffix(&[0]).unwrap();
+        let mut bundle = [pem, b"\n", pem].concat();
+        bundle.push(0);
+        assert_eq!(check_pem(&bundle), Ok(()));
+    }
+
+    #[test]
+    fn der_that_is_no_certificate_fails() {
+        let x509 =
+            Err("does not have the structure of an X.509 certificate (its fields are not checked)");
+        // an empty SEQUENCE
+        assert_eq!(check_pem(pem(&[0x30, 0x00]).as_bytes()), x509);
+        // a SEQUENCE of three SEQUENCEs
+        let der = [0x30, 0x06, 0x30, 0x00, 0x30, 0x00, 0x30, 0x00];
+        assert_eq!(check_pem(pem(&der).as_bytes()), x509);
+        let der = [0x30, 0x06, 0x30, 0x00, 0x30, 0x00, 0x03, 0x00];
+        assert_eq!(check_pem(pem(&der).as_bytes()), Ok(()));
+
+        assert_eq!(
+            check_pem(pem(&[0x02, 0x01, 0x00]).as_bytes()),
+            Err("is not a DER certificate")
+        );
+        assert_eq!(
+            check_pem(pem(&[0x30, 0x03, 0x30, 0x00]).as_bytes()),
+            Err("has a truncated certificate")
+        );
+    }
+
+    #[test]
+    fn malformed_pem_fails() {
+        assert_eq!(check_pem(b""), Err("contains no certificate"));
+        assert_eq!(check_pem(b"MAA=\n"), Err("missing BEGIN CERTIFICATE line"));
+        let pem = format!("{PEM_BEGIN}\nMAA=\n");
+        assert_eq!(
+            check_pem(pem.as_bytes()),
+            Err("missing END CERTIFICATE line")
+        );
+        let pem = format!("{PEM_BEGIN}\nMA*=\n{PEM_END}");
+        assert_eq!(check_pem(pem.as_bytes()), Err("contains invalid base64"));
+    }
+}
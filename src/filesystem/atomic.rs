@@ -0,0 +1,108 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use littlefs2::io::Error;
+use littlefs2::object_safe::DynFilesystem;
+use littlefs2::path::{Path, PathBuf};
+use log::warn;
+
+const TMP_SUFFIX: &str = ".tmp";
+const BACKUP_SUFFIX: &str = ".bak";
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum ReadError<E> {
+    #[error("Failed to read file: {}", .0.code())]
+    Read(Error),
+    #[error("Failed to parse file: {0}")]
+    Parse(E),
+}
+
+/// Which copy [`read_or_backup`] used.
+#[derive(Debug, Clone)]
+pub enum Origin<E> {
+    Primary,
+    /// The `.bak` copy, because the file itself failed with this.
+    Backup(ReadError<E>),
+}
+
+/// Replaces `path` by `contents`, so that a power loss at any point leaves
+/// either the old or the new file behind.
+///
+/// The contents go to `<path>.tmp` first, which is synced and then renamed
+/// over `path`, as littlefs renames are atomic. With `keep_backup` the old
+/// file is moved to `<path>.bak` instead, for [`read_or_backup`].
+pub fn replace_file(
+    fs: &dyn DynFilesystem,
+    path: &Path,
+    contents: &[u8],
+    keep_backup: bool,
+) -> Result<(), Error> {
+    let tmp = with_suffix(path, TMP_SUFFIX)?;
+    fs.create_file_and_then(&tmp, &mut |file| {
+        file.write_all(contents)?;
+        file.sync()
+    })?;
+    if keep_backup && fs.exists(path) {
+        fs.rename(path, &with_suffix(path, BACKUP_SUFFIX)?)?;
+    }
+    fs.rename(&tmp, path)
+}
+
+/// Reads `path`, of at most `max_len` bytes, and parses it.
+///
+/// If the file is missing, unreadable or rejected by `parse`, its `.bak`
+/// copy is tried instead. If neither works, the error of the file itself is
+/// returned.
+///
+/// A backup that is used stays where it is, so the next [`replace_file`]
+/// that keeps a backup overwrites it with the broken file unless it is put
+/// back with [`restore_backup`] first.
+pub fn read_or_backup<T, E>(
+    fs: &dyn DynFilesystem,
+    path: &Path,
+    max_len: usize,
+    mut parse: impl FnMut(&[u8]) -> Result<T, E>,
+) -> Result<(T, Origin<E>), ReadError<E>> {
+    let error = match read_file(fs, path, max_len) {
+        Ok(data) => match parse(&data) {
+            Ok(value) => return Ok((value, Origin::Primary)),
+            Err(e) => ReadError::Parse(e),
+        },
+        Err(e) => ReadError::Read(e),
+    };
+
+    let backup = with_suffix(path, BACKUP_SUFFIX).map_err(ReadError::Read)?;
+    let Ok(data) = read_file(fs, &backup, max_len) else {
+        return Err(error);
+    };
+    let Ok(value) = parse(&data) else {
+        return Err(error);
+    };
+    warn!("Using {backup}, as {path} is unusable");
+    Ok((value, Origin::Backup(error)))
+}
+
+/// Moves `<path>.bak` over `path`.
+pub fn restore_backup(fs: &dyn DynFilesystem, path: &Path) -> Result<(), Error> {
+    fs.rename(&with_suffix(path, BACKUP_SUFFIX)?, path)
+}
+
+/// Reads all of `path`, failing if it is larger than `max_len`.
+pub fn read_file(fs: &dyn DynFilesystem, path: &Path, max_len: usize) -> Result<Vec<u8>, Error> {
+    fs.open_file_and_then(path, &mut |file| {
+        let len = file.len()?;
+        if len > max_len {
+            return Err(Error::FILE_TOO_BIG);
+        }
+        let mut data = vec![0; len];
+        file.read_exact(&mut data)?;
+        Ok(data)
+    })
+}
+
+/// `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> Result<PathBuf, Error> {
+    let mut name = String::from(path.as_str());
+    name.push_str(suffix);
+    PathBuf::try_from(name.as_str()).map_err(|_| Error::FILENAME_TOO_LONG)
+}
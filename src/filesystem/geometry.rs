@@ -0,0 +1,25 @@
+//! littlefs configuration of [`AppStorage`](super::AppStorage).
+//!
+//! `tools/fsimage` includes this file to build images for the storage
+//! partition, so it may only use `core` and `typenum`.
+
+/// Minimum read, in bytes.
+pub const READ_SIZE: usize = 16;
+/// Minimum write, in bytes.
+pub const WRITE_SIZE: usize = 16;
+/// The erase size of the ESP32 flash.
+pub const BLOCK_SIZE: usize = 4096;
+/// Size of the littlefs filesystem. littlefs2 needs the block count at
+/// compile time, so a partition can be larger but not smaller.
+pub const FILESYSTEM_SIZE: usize = 300 * 1024; // 300 KB
+pub const BLOCK_COUNT: usize = FILESYSTEM_SIZE / BLOCK_SIZE;
+
+/// Size of the littlefs read, write and per-file caches, in bytes.
+pub type CacheSize = typenum::U256;
+/// Size of the block allocation lookahead buffer, in 8 byte units.
+pub type LookaheadSize = typenum::U1;
+
+const _: () = {
+    assert!(BLOCK_SIZE % READ_SIZE == 0 && BLOCK_SIZE % WRITE_SIZE == 0);
+    assert!(FILESYSTEM_SIZE % BLOCK_SIZE == 0);
+};
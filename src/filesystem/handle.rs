@@ -0,0 +1,236 @@
+#[cfg(target_arch = "xtensa")]
+use super::{ALLOC, AppStorage, FILESYSTEM, STORAGE};
+use super::{Health, StorageError, read_file, replace_file};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use littlefs2::fs::DirEntry;
+#[cfg(target_arch = "xtensa")]
+use littlefs2::fs::{Allocation, Filesystem};
+use littlefs2::io::Error;
+use littlefs2::object_safe::{DynFile, DynFilesystem};
+use littlefs2::path::{Path, PathBuf};
+#[cfg(target_arch = "xtensa")]
+use log::{info, warn};
+use once_cell::sync::OnceCell;
+
+/// Jobs waiting for [`FsHandle::serve`].
+const JOB_QUEUE_LEN: usize = 4;
+
+static FS: OnceCell<FsHandle> = OnceCell::new();
+
+type Job = Box<dyn FnOnce(&dyn DynFilesystem) + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+pub enum MountError {
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error("Failed to format filesystem: {}", .0.code())]
+    Format(Error),
+    #[error("Filesystem is already mounted")]
+    AlreadyMounted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum Unavailable {
+    #[error("Filesystem is not mounted")]
+    NotMounted,
+    #[error("Filesystem is in use")]
+    Busy,
+}
+
+/// Block usage of the filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub block_size: usize,
+    pub total_blocks: usize,
+    pub free_blocks: usize,
+}
+
+impl Usage {
+    pub fn of(fs: &dyn DynFilesystem) -> Result<Self, Error> {
+        Ok(Self {
+            block_size: fs.total_space() / fs.total_blocks(),
+            total_blocks: fs.total_blocks(),
+            free_blocks: fs.available_blocks()?,
+        })
+    }
+
+    pub fn used_blocks(&self) -> usize {
+        self.total_blocks - self.free_blocks
+    }
+}
+
+/// The littlefs filesystem mounted by [`mount`], shared by all tasks.
+///
+/// Each call locks the filesystem for one operation, so tasks can use it
+/// concurrently without ever remounting it. The lock is never held across an
+/// await point.
+///
+/// littlefs is synchronous, so an operation blocks the executor it runs on
+/// until it is done, for hundreds of milliseconds when littlefs erases
+/// blocks to compact them. [`Self::run`] moves writes to the task running
+/// [`Self::serve`], which can have an executor, or a core, of its own. On
+/// the ESP32 each program and erase still stalls the other core too, as it
+/// disables the flash cache, but only for that one flash operation.
+pub struct FsHandle {
+    fs: Mutex<CriticalSectionRawMutex, Mounted>,
+    jobs: Channel<CriticalSectionRawMutex, Job, JOB_QUEUE_LEN>,
+    /// Whether a task runs [`Self::serve`].
+    serving: AtomicBool,
+}
+
+struct Mounted(&'static dyn DynFilesystem);
+
+// SAFETY: the filesystem is only reached through the mutex of its
+// `FsHandle`, so no two tasks use it at the same time.
+unsafe impl Send for Mounted {}
+
+/// Mounts the filesystem on the storage partition, formatting it first if it
+/// does not mount. Call once at boot.
+#[cfg(target_arch = "xtensa")]
+pub fn mount() -> Result<&'static FsHandle, MountError> {
+    if FS.get().is_some() {
+        return Err(MountError::AlreadyMounted);
+    }
+    let storage = STORAGE.init(AppStorage::open()?);
+    let alloc = ALLOC.init(Allocation::new());
+    let mut format_error = None;
+    let fs = Filesystem::mount_or_else(alloc, storage, |e, storage| {
+        warn!("Failed to mount filesystem: {:?}. Formatting it", e.code());
+        Filesystem::format(storage).inspect_err(|&e| format_error = Some(e))
+    })
+    .map_err(|e| MountError::Format(format_error.unwrap_or(e)))?;
+    let fs = FILESYSTEM.init(fs);
+    match Health::of(fs) {
+        Ok(health) => info!("Mounted filesystem: {health}"),
+        Err(e) => warn!("Mounted filesystem, but cannot walk it: {:?}", e.code()),
+    }
+    Ok(FS.get_or_init(|| FsHandle::new(fs)))
+}
+
+/// Returns the filesystem mounted by [`mount`].
+pub fn handle() -> Result<&'static FsHandle, Unavailable> {
+    FS.get().ok_or(Unavailable::NotMounted)
+}
+
+impl FsHandle {
+    #[cfg(any(target_arch = "xtensa", test))]
+    pub(super) fn new(fs: &'static dyn DynFilesystem) -> Self {
+        Self {
+            fs: Mutex::new(Mounted(fs)),
+            jobs: Channel::new(),
+            serving: AtomicBool::new(false),
+        }
+    }
+
+    /// Runs `f` on the filesystem.
+    pub async fn with<R>(&self, f: impl FnOnce(&dyn DynFilesystem) -> R) -> R {
+        f(self.fs.lock().await.0)
+    }
+
+    /// Runs `f` on the filesystem in the task running [`Self::serve`], so that
+    /// it blocks the executor of that task instead of the caller's. Until a
+    /// task serves the filesystem, `f` runs right away like with
+    /// [`Self::with`].
+    ///
+    /// `f` runs to completion even if the returned future is dropped.
+    pub async fn run<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&dyn DynFilesystem) -> R + Send + 'static,
+    ) -> R {
+        if !self.serving.load(Ordering::Acquire) {
+            return self.with(f).await;
+        }
+        let done = Arc::new(Signal::<CriticalSectionRawMutex, R>::new());
+        let result = done.clone();
+        self.jobs
+            .send(Box::new(move |fs| result.signal(f(fs))))
+            .await;
+        done.wait().await
+    }
+
+    /// Runs the jobs passed to [`Self::run`], forever. Call from one task
+    /// only, see [`fs_worker_task`].
+    pub async fn serve(&self) -> ! {
+        self.serving.store(true, Ordering::Release);
+        loop {
+            let job = self.jobs.receive().await;
+            self.with(job).await;
+        }
+    }
+
+    /// Runs `f` on the filesystem if no task is using it, for code that
+    /// cannot await, like the initialisation of a `Lazy`.
+    pub fn try_with<R>(&self, f: impl FnOnce(&dyn DynFilesystem) -> R) -> Result<R, Unavailable> {
+        let fs = self.fs.try_lock().map_err(|_| Unavailable::Busy)?;
+        Ok(f(fs.0))
+    }
+
+    /// Opens `path` for reading and runs `f` on it.
+    pub async fn open<R>(
+        &self,
+        path: &Path,
+        f: impl FnOnce(&dyn DynFile) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let mut f = Some(f);
+        self.with(|fs| fs.open_file_and_then(path, &mut |file| f.take().unwrap()(file)))
+            .await
+    }
+
+    /// Reads all of `path`, failing if it is larger than `max_len`.
+    pub async fn read(&self, path: &Path, max_len: usize) -> Result<Vec<u8>, Error> {
+        self.with(|fs| read_file(fs, path, max_len)).await
+    }
+
+    /// Replaces `path` by `contents`, atomically.
+    pub async fn write(&self, path: &Path, contents: &[u8]) -> Result<(), Error> {
+        let path = PathBuf::from(path);
+        let contents = contents.to_vec();
+        self.run(move |fs| replace_file(fs, &path, &contents, false))
+            .await
+    }
+
+    /// Lists the entries of the directory `path`, without `.` and `..`.
+    pub async fn list(&self, path: &Path) -> Result<Vec<DirEntry>, Error> {
+        self.with(|fs| {
+            fs.read_dir_and_then(path, &mut |entries| {
+                entries
+                    .filter(|entry| {
+                        !matches!(entry, Ok(e) if matches!(e.file_name().as_str(), "." | ".."))
+                    })
+                    .collect()
+            })
+        })
+        .await
+    }
+
+    pub async fn remove(&self, path: &Path) -> Result<(), Error> {
+        let path = PathBuf::from(path);
+        self.run(move |fs| fs.remove(&path)).await
+    }
+
+    pub async fn usage(&self) -> Result<Usage, Error> {
+        self.with(Usage::of).await
+    }
+
+    /// Usage, file counts and flash statistics, see [`Health`].
+    pub async fn health(&self) -> Result<Health, Error> {
+        self.with(Health::of).await
+    }
+}
+
+/// Serves the filesystem mounted by [`mount`], see [`FsHandle::serve`].
+///
+/// Spawn once, preferably on the app core, so that flash writes block the
+/// tasks on the main executor for one program or erase at a time rather
+/// than for whole littlefs operations.
+#[embassy_executor::task]
+pub async fn fs_worker_task(fs: &'static FsHandle) {
+    fs.serve().await
+}
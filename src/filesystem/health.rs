@@ -0,0 +1,175 @@
+use super::{Usage, geometry, guard_violations};
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+use littlefs2::fs::FileType;
+use littlefs2::io::Error;
+use littlefs2::object_safe::DynFilesystem;
+use littlefs2::path::{Path, PathBuf};
+
+// flash operations and failures of any `AppStorage` since boot
+static READS: AtomicU32 = AtomicU32::new(0);
+static WRITES: AtomicU32 = AtomicU32::new(0);
+static ERASES: AtomicU32 = AtomicU32::new(0);
+static READ_ERRORS: AtomicU32 = AtomicU32::new(0);
+static WRITE_ERRORS: AtomicU32 = AtomicU32::new(0);
+static ERASE_ERRORS: AtomicU32 = AtomicU32::new(0);
+static BLOCK_ERASES: [AtomicU32; geometry::BLOCK_COUNT] =
+    [const { AtomicU32::new(0) }; geometry::BLOCK_COUNT];
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Operation {
+    Read,
+    Write,
+    Erase,
+}
+
+/// Counts an operation of the storage layer. Erases are counted for each
+/// littlefs block in `off..off + len`.
+pub(super) fn record<T, E>(operation: Operation, off: usize, len: usize, result: &Result<T, E>) {
+    let (count, errors) = match operation {
+        Operation::Read => (&READS, &READ_ERRORS),
+        Operation::Write => (&WRITES, &WRITE_ERRORS),
+        Operation::Erase => (&ERASES, &ERASE_ERRORS),
+    };
+    count.fetch_add(1, Ordering::Relaxed);
+    if result.is_err() {
+        errors.fetch_add(1, Ordering::Relaxed);
+    }
+    if let Operation::Erase = operation {
+        let blocks = off / geometry::BLOCK_SIZE..(off + len).div_ceil(geometry::BLOCK_SIZE);
+        for erases in BLOCK_ERASES.get(blocks).unwrap_or_default() {
+            erases.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Flash operations of the storage layer since boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FlashCounters {
+    pub reads: u32,
+    pub writes: u32,
+    pub erases: u32,
+    pub read_errors: u32,
+    pub write_errors: u32,
+    pub erase_errors: u32,
+    /// Accesses refused for being outside the partition, see
+    /// [`guard_violations`].
+    pub guard_violations: u32,
+}
+
+impl FlashCounters {
+    pub fn now() -> Self {
+        let load = |counter: &AtomicU32| counter.load(Ordering::Relaxed);
+        Self {
+            reads: load(&READS),
+            writes: load(&WRITES),
+            erases: load(&ERASES),
+            read_errors: load(&READ_ERRORS),
+            write_errors: load(&WRITE_ERRORS),
+            erase_errors: load(&ERASE_ERRORS),
+            guard_violations: guard_violations(),
+        }
+    }
+
+    pub fn errors(&self) -> u32 {
+        self.read_errors + self.write_errors + self.erase_errors + self.guard_violations
+    }
+}
+
+/// How often each littlefs block was erased since boot, by block number.
+///
+/// The counts are kept in RAM only, so they show which blocks the current
+/// workload erases, not how worn the flash is.
+pub fn block_erases_since_boot() -> [u32; geometry::BLOCK_COUNT] {
+    core::array::from_fn(|block| BLOCK_ERASES[block].load(Ordering::Relaxed))
+}
+
+/// Usage of the filesystem and flash activity since boot, for logs and
+/// metrics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    pub usage: Usage,
+    pub files: usize,
+    pub dirs: usize,
+    /// Total size of all files.
+    pub file_bytes: usize,
+    pub flash: FlashCounters,
+    /// The block erased most often since boot, the first if there are
+    /// several, and how often it was erased.
+    pub most_erased_since_boot: (usize, u32),
+}
+
+impl Health {
+    /// Walks all of `fs` to count its files.
+    pub fn of(fs: &dyn DynFilesystem) -> Result<Self, Error> {
+        let mut health = Self {
+            usage: Usage::of(fs)?,
+            files: 0,
+            dirs: 0,
+            file_bytes: 0,
+            flash: FlashCounters::now(),
+            most_erased_since_boot: (0, 0),
+        };
+        health.count(fs, littlefs2::path!("/"))?;
+        health.most_erased_since_boot = block_erases_since_boot()
+            .into_iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|&(_, erases)| erases)
+            .unwrap_or_default();
+        Ok(health)
+    }
+
+    fn count(&mut self, fs: &dyn DynFilesystem, dir: &Path) -> Result<(), Error> {
+        let mut subdirs = Vec::new();
+        fs.read_dir_and_then(dir, &mut |entries| {
+            for entry in entries {
+                let entry = entry?;
+                match entry.file_type() {
+                    FileType::File => {
+                        self.files += 1;
+                        self.file_bytes += entry.metadata().len();
+                    }
+                    FileType::Dir => match entry.file_name().as_str() {
+                        "." | ".." => (),
+                        _ => subdirs.push(PathBuf::from(entry.path())),
+                    },
+                }
+            }
+            Ok(())
+        })?;
+        for subdir in subdirs {
+            self.dirs += 1;
+            self.count(fs, &subdir)?;
+        }
+        Ok(())
+    }
+}
+
+/// One line, e.g. `12 of 75 blocks used, 9 files in 3 directories (20144
+/// bytes), 1530 reads, 212 writes, 14 erases, 0 errors, block 7 erased 3
+/// times since boot`.
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (block, erases) = self.most_erased_since_boot;
+        write!(
+            f,
+            "{} of {} blocks used, {} files in {} directories ({} bytes), {} reads, {} writes, \
+            {} erases, {} errors",
+            self.usage.used_blocks(),
+            self.usage.total_blocks,
+            self.files,
+            self.dirs,
+            self.file_bytes,
+            self.flash.reads,
+            self.flash.writes,
+            self.flash.erases,
+            self.flash.errors(),
+        )?;
+        if erases > 0 {
+            write!(f, ", block {block} erased {erases} times since boot")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,381 @@
+use super::handle;
+use alloc::format;
+use alloc::vec::Vec;
+use core::fmt::{self, Write as _};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use littlefs2::fs::FileOpenFlags;
+use littlefs2::io::Error;
+use littlefs2::object_safe::DynFilesystem;
+use littlefs2::path::{Path, PathBuf};
+use log::{Level, LevelFilter, warn};
+
+/// Directory holding the log files.
+const LOG_DIR: &Path = littlefs2::path!("/logs");
+/// The file records are appended to. Rotated files are `/logs/1.log`, the
+/// newest, to `/logs/<rotated_files>.log`.
+pub const CURRENT_LOG: &Path = littlefs2::path!("/logs/current.log");
+/// Maximum length of a record, including the newline. Longer records are
+/// truncated.
+pub const LINE_MAX_LEN: usize = 256;
+/// Records waiting for [`logfile_task`]. Records logged while it is full are
+/// dropped and counted in [`dropped_records`].
+const QUEUE_LEN: usize = 32;
+/// Most bytes written to the file at once.
+const BATCH_MAX_LEN: usize = 2048;
+const TRUNCATED: &str = "...";
+
+/// When to rotate the log and how many old files to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// The current file is rotated before a write would make it larger.
+    pub max_file_size: usize,
+    /// Number of rotated files to keep. With 0 the current file is deleted.
+    pub rotated_files: usize,
+}
+
+/// 4 files of at most 16 KB, a fifth of the filesystem.
+pub const DEFAULT_ROTATION: Rotation = Rotation {
+    max_file_size: 16 * 1024,
+    rotated_files: 3,
+};
+
+type Line = heapless::String<LINE_MAX_LEN>;
+
+static QUEUE: Channel<CriticalSectionRawMutex, Line, QUEUE_LEN> = Channel::new();
+/// Most verbose level written to the file, as a [`LevelFilter`]. Records are
+/// only queued once [`logfile_task`] runs.
+static LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Off as usize);
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Returns how many records were dropped since boot because the queue of
+/// [`logfile_task`] was full.
+pub fn dropped_records() -> u32 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Whether records of `level` go to the log file.
+pub fn enabled(level: Level) -> bool {
+    level as usize <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Queues a record for [`logfile_task`], without blocking, so that it can
+/// be called from the logger in any context.
+pub fn push(level: Level, record: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    if QUEUE.try_send(format_line(record)).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Writes the records passed to [`push`] with at most `level` to
+/// [`CURRENT_LOG`], rotating it as set by `rotation`.
+///
+/// The files are kept across reboots. Spawn once, after the filesystem is
+/// mounted.
+#[embassy_executor::task]
+pub async fn logfile_task(level: LevelFilter, rotation: Rotation) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+    let mut failing = false;
+    let mut dropped = 0;
+    loop {
+        let mut batch = Vec::with_capacity(BATCH_MAX_LEN);
+        batch.extend_from_slice(QUEUE.receive().await.as_bytes());
+        while batch.len() + LINE_MAX_LEN <= BATCH_MAX_LEN {
+            match QUEUE.try_receive() {
+                Ok(line) => batch.extend_from_slice(line.as_bytes()),
+                Err(_) => break,
+            }
+        }
+        let total_dropped = dropped_records();
+        if total_dropped != dropped {
+            let note = format!("{} log records dropped\n", total_dropped - dropped);
+            batch.extend_from_slice(note.as_bytes());
+            dropped = total_dropped;
+        }
+
+        let result = match handle() {
+            Ok(fs) => fs.run(move |fs| append(fs, &rotation, &batch)).await,
+            Err(_) => Err(Error::IO),
+        };
+        // only reported once, as the warning goes to the log file too
+        match result {
+            Err(e) if !failing => {
+                warn!("Failed to write log file: {}", e.code());
+                failing = true;
+            }
+            Ok(()) if failing => failing = false,
+            _ => (),
+        }
+    }
+}
+
+/// Appends `data` to [`CURRENT_LOG`], rotating it first if it would grow
+/// beyond `rotation.max_file_size`.
+pub fn append(fs: &dyn DynFilesystem, rotation: &Rotation, data: &[u8]) -> Result<(), Error> {
+    match fs.create_dir(LOG_DIR) {
+        Err(e) if e != Error::ENTRY_ALREADY_EXISTED => return Err(e),
+        _ => (),
+    }
+    let len = match fs.metadata(CURRENT_LOG) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e == Error::NO_SUCH_ENTRY => 0,
+        Err(e) => return Err(e),
+    };
+    if len > 0 && len + data.len() > rotation.max_file_size {
+        rotate(fs, rotation)?;
+    }
+    let flags = FileOpenFlags::WRITE | FileOpenFlags::CREATE | FileOpenFlags::APPEND;
+    fs.open_file_with_flags_and_then(flags, CURRENT_LOG, &mut |file| file.write_all(data))
+}
+
+/// Shifts the rotated files up by one, deleting the oldest, and moves
+/// [`CURRENT_LOG`] to `/logs/1.log`.
+///
+/// Every step is an atomic rename, so a power loss leaves at worst a gap in
+/// the numbering.
+pub fn rotate(fs: &dyn DynFilesystem, rotation: &Rotation) -> Result<(), Error> {
+    if rotation.rotated_files == 0 {
+        return fs.remove(CURRENT_LOG);
+    }
+    let oldest = rotated_path(rotation.rotated_files)?;
+    match fs.remove(&oldest) {
+        Err(e) if e != Error::NO_SUCH_ENTRY => return Err(e),
+        _ => (),
+    }
+    for n in (1..rotation.rotated_files).rev() {
+        match fs.rename(&rotated_path(n)?, &rotated_path(n + 1)?) {
+            Err(e) if e != Error::NO_SUCH_ENTRY => return Err(e),
+            _ => (),
+        }
+    }
+    fs.rename(CURRENT_LOG, &rotated_path(1)?)
+}
+
+/// Path of the `n`th newest rotated file.
+pub fn rotated_path(n: usize) -> Result<PathBuf, Error> {
+    PathBuf::try_from(format!("{LOG_DIR}/{n}.log").as_str()).map_err(|_| Error::FILENAME_TOO_LONG)
+}
+
+/// Formats `record` as a line of the log file, truncated to
+/// [`LINE_MAX_LEN`].
+fn format_line(record: fmt::Arguments) -> Line {
+    let mut line = TruncatingWriter(Line::new());
+    // never fails, the writer truncates instead
+    let _ = line.write_fmt(record);
+    let mut line = line.0;
+    line.push('\n').unwrap();
+    line
+}
+
+/// Writes as much as fits into the line, leaving room for the newline, and
+/// ends a truncated line with `...`.
+struct TruncatingWriter(Line);
+
+impl fmt::Write for TruncatingWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = LINE_MAX_LEN - 1 - self.0.len();
+        if s.len() <= room {
+            self.0.push_str(s).unwrap();
+            return Ok(());
+        }
+        let mut end = room.saturating_sub(TRUNCATED.len());
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.0.push_str(&s[..end]).unwrap();
+        let room = LINE_MAX_LEN - 1 - self.0.len();
+        // the rest of the record is dropped
+        self.0
+            .push_str(&TRUNCATED[..room.min(TRUNCATED.len())])
+            .unwrap();
+        Err(fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::ram_flash::formatted_storage;
+    use crate::filesystem::read_file;
+    use alloc::string::String;
+    use littlefs2::fs::Filesystem;
+
+    const ROTATION: Rotation = Rotation {
+        max_file_size: 16,
+        rotated_files: 2,
+    };
+
+    /// Runs `f` on a freshly formatted filesystem.
+    fn with_fs(f: impl FnOnce(&dyn DynFilesystem)) {
+        let mut storage = formatted_storage();
+        Filesystem::mount_and_then(&mut storage, |fs| {
+            f(fs);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// The contents of `path`, or `None` if it does not exist.
+    fn contents(fs: &dyn DynFilesystem, path: &Path) -> Option<String> {
+        match read_file(fs, path, 1024) {
+            Ok(data) => Some(String::from_utf8(data).unwrap()),
+            Err(e) if e == Error::NO_SUCH_ENTRY => None,
+            Err(e) => panic!("reading {path}: {}", e.code()),
+        }
+    }
+
+    fn rotated(fs: &dyn DynFilesystem, n: usize) -> Option<String> {
+        contents(fs, &rotated_path(n).unwrap())
+    }
+
+    #[test]
+    fn append_rotates_before_exceeding_the_file_size() {
+        with_fs(|fs| {
+            append(fs, &ROTATION, b"0123456789\n").unwrap();
+            append(fs, &ROTATION, b"abcd\n").unwrap();
+            assert_eq!(contents(fs, CURRENT_LOG).unwrap(), "0123456789\nabcd\n");
+            assert_eq!(rotated(fs, 1), None);
+
+            // one more byte than fits
+            append(fs, &ROTATION, b"e\n").unwrap();
+            assert_eq!(contents(fs, CURRENT_LOG).unwrap(), "e\n");
+            assert_eq!(rotated(fs, 1).unwrap(), "0123456789\nabcd\n");
+
+            append(fs, &ROTATION, b"fghijklmnopqrst\n").unwrap();
+            append(fs, &ROTATION, b"u\n").unwrap();
+            assert_eq!(contents(fs, CURRENT_LOG).unwrap(), "u\n");
+            assert_eq!(rotated(fs, 1).unwrap(), "fghijklmnopqrst\n");
+            assert_eq!(rotated(fs, 2).unwrap(), "e\n");
+            assert_eq!(rotated(fs, 3), None);
+        });
+    }
+
+    #[test]
+    fn records_larger_than_a_file_are_written_to_their_own() {
+        with_fs(|fs| {
+            append(fs, &ROTATION, b"0123456789abcdefghij\n").unwrap();
+            append(fs, &ROTATION, b"0123456789abcdefghij\n").unwrap();
+            assert_eq!(contents(fs, CURRENT_LOG).unwrap(), "0123456789abcdefghij\n");
+            assert_eq!(rotated(fs, 1).unwrap(), "0123456789abcdefghij\n");
+            assert_eq!(rotated(fs, 2), None);
+        });
+    }
+
+    #[test]
+    fn without_rotated_files_the_current_file_is_deleted() {
+        let rotation = Rotation {
+            rotated_files: 0,
+            ..ROTATION
+        };
+        with_fs(|fs| {
+            append(fs, &rotation, b"0123456789\n").unwrap();
+            append(fs, &rotation, b"abcdefghij\n").unwrap();
+            assert_eq!(contents(fs, CURRENT_LOG).unwrap(), "abcdefghij\n");
+            assert_eq!(rotated(fs, 1), None);
+        });
+    }
+
+    #[test]
+    fn rotation_keeps_the_newest_files() {
+        let rotation = Rotation {
+            rotated_files: 4,
+            ..ROTATION
+        };
+        with_fs(|fs| {
+            for record in ["a\n", "b\n", "c\n", "d\n", "e\n", "f\n"] {
+                append(fs, &rotation, record.as_bytes()).unwrap();
+                rotate(fs, &rotation).unwrap();
+            }
+            assert_eq!(contents(fs, CURRENT_LOG), None);
+            for (n, record) in [(1, "f\n"), (2, "e\n"), (3, "d\n"), (4, "c\n")] {
+                assert_eq!(rotated(fs, n).unwrap(), record);
+            }
+            assert_eq!(rotated(fs, 5), None);
+        });
+    }
+
+    #[test]
+    fn rotation_after_a_crash_closes_the_gap() {
+        let rotation = Rotation {
+            rotated_files: 3,
+            ..ROTATION
+        };
+        with_fs(|fs| {
+            // power lost after `1.log` was renamed to `2.log`, before the
+            // current file became `1.log`
+            append(fs, &rotation, b"old\n").unwrap();
+            rotate(fs, &rotation).unwrap();
+            fs.rename(&rotated_path(1).unwrap(), &rotated_path(2).unwrap())
+                .unwrap();
+            append(fs, &rotation, b"current\n").unwrap();
+
+            rotate(fs, &rotation).unwrap();
+            assert_eq!(contents(fs, CURRENT_LOG), None);
+            assert_eq!(rotated(fs, 1).unwrap(), "current\n");
+            assert_eq!(rotated(fs, 2), None);
+            assert_eq!(rotated(fs, 3).unwrap(), "old\n");
+
+            append(fs, &rotation, b"new\n").unwrap();
+            rotate(fs, &rotation).unwrap();
+            assert_eq!(rotated(fs, 1).unwrap(), "new\n");
+            assert_eq!(rotated(fs, 2).unwrap(), "current\n");
+            assert_eq!(rotated(fs, 3), None);
+        });
+    }
+
+    #[test]
+    fn rotation_without_a_current_file_fails() {
+        with_fs(|fs| {
+            let e = rotate(fs, &ROTATION).unwrap_err();
+            assert!(e == Error::NO_SUCH_ENTRY, "{}", e.code());
+        });
+    }
+
+    #[test]
+    fn records_that_fit_are_not_truncated() {
+        let record = "x".repeat(LINE_MAX_LEN - 1);
+        let line = format_line(format_args!("{record}"));
+        assert_eq!(line.len(), LINE_MAX_LEN);
+        assert_eq!(line.strip_suffix('\n').unwrap(), record);
+
+        // the same split over several writes
+        let (head, tail) = record.split_at(100);
+        let line = format_line(format_args!("{head}{tail}"));
+        assert_eq!(line.strip_suffix('\n').unwrap(), record);
+    }
+
+    #[test]
+    fn longer_records_are_truncated() {
+        let record = "x".repeat(LINE_MAX_LEN);
+        let line = format_line(format_args!("{record}"));
+        assert_eq!(line.len(), LINE_MAX_LEN);
+        let kept = LINE_MAX_LEN - 1 - TRUNCATED.len();
+        assert_eq!(line[..kept], record[..kept]);
+        assert!(line.ends_with("...\n"));
+
+        // the marker is shortened when a previous write left less room
+        let head = "x".repeat(LINE_MAX_LEN - 3);
+        let line = format_line(format_args!("{head}{}", "yyyy"));
+        assert_eq!(line.len(), LINE_MAX_LEN);
+        assert!(line.ends_with("x..\n"));
+    }
+
+    #[test]
+    fn truncation_does_not_split_characters() {
+        // the cut at 252 bytes falls into a 2, 3 and 4 byte character
+        for c in ['é', '€', '𝄞'] {
+            let record: String = core::iter::once('a')
+                .chain(core::iter::repeat_n(c, LINE_MAX_LEN))
+                .collect();
+            let line = format_line(format_args!("{record}"));
+            let kept = line.strip_suffix("...\n").unwrap();
+            assert!(record.starts_with(kept));
+            assert!(kept.len() < LINE_MAX_LEN - 1 - TRUNCATED.len());
+            assert!(kept.len() + c.len_utf8() > LINE_MAX_LEN - 1 - TRUNCATED.len());
+        }
+    }
+}
@@ -0,0 +1,292 @@
+mod atomic;
+pub mod geometry;
+mod handle;
+mod health;
+pub mod logfile;
+pub mod partition;
+#[cfg(test)]
+pub mod power_loss;
+#[cfg(test)]
+pub mod ram_flash;
+#[cfg(test)]
+pub mod slow_flash;
+#[cfg(test)]
+pub mod stall;
+
+pub use atomic::{Origin, ReadError, read_file, read_or_backup, replace_file, restore_backup};
+pub use geometry::FILESYSTEM_SIZE;
+#[cfg(target_arch = "xtensa")]
+pub use handle::mount;
+pub use handle::{FsHandle, MountError, Unavailable, Usage, fs_worker_task, handle};
+pub use health::{FlashCounters, Health, block_erases_since_boot};
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use embedded_storage::nor_flash::NorFlash;
+#[cfg(target_arch = "xtensa")]
+use esp_storage::FlashStorage;
+use health::Operation;
+#[cfg(target_arch = "xtensa")]
+use littlefs2::fs::{Allocation, Filesystem};
+use littlefs2::io::Error;
+use log::error;
+use partition::{SUBTYPE_LITTLEFS, TYPE_DATA};
+#[cfg(target_arch = "xtensa")]
+use static_cell::StaticCell;
+
+/// Label of the partition holding the filesystem in `partitions.csv`.
+pub const PARTITION_LABEL: &str = "storage";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum GeometryError {
+    #[error("Partition at {offset:#x} is not aligned to the {erase_size} byte erase size")]
+    Misaligned { offset: u32, erase_size: usize },
+    #[error("Partition of {size} bytes is smaller than the {FILESYSTEM_SIZE} byte filesystem")]
+    TooSmall { size: usize },
+    #[error("Partition ends beyond the {capacity} byte flash")]
+    OutOfBounds { capacity: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum StorageError {
+    #[error(transparent)]
+    PartitionTable(#[from] partition::Error),
+    #[error("No partition labelled `{PARTITION_LABEL}` in the partition table")]
+    NotFound,
+    #[error(
+        "Partition `{PARTITION_LABEL}` has type {kind:#04x} and subtype {subtype:#04x} \
+        instead of data and littlefs"
+    )]
+    WrongType { kind: u8, subtype: u8 },
+    #[error("Partition `{PARTITION_LABEL}` is unusable: {0}")]
+    Geometry(#[from] GeometryError),
+}
+
+/// Flash accesses refused by the guard of any [`AppStorage`] since boot.
+static GUARD_VIOLATIONS: AtomicU32 = AtomicU32::new(0);
+
+/// Returns how many flash accesses [`AppStorage`] has refused since boot.
+pub fn guard_violations() -> u32 {
+    GUARD_VIOLATIONS.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Access {
+    Read,
+    Write,
+    Erase,
+}
+
+/// littlefs storage on the region of a NOR flash that holds the filesystem.
+///
+/// The littlefs configuration is in [`geometry`], shared with the host image
+/// tool. Its block size must be a multiple of the erase size of `F`. littlefs
+/// erases blocks before writing them, so `F` only needs to support plain NOR
+/// writes.
+///
+/// Every access is checked against the partition before it reaches the
+/// flash, so a littlefs bug or a wrong block count cannot touch the
+/// application. Accesses outside the partition and erases of partial sectors
+/// fail with [`Error::IO`] and are counted in [`guard_violations`].
+pub struct AppStorage<F: NorFlash> {
+    flash: F,
+    /// Start of the partition in `flash`.
+    offset: u32,
+    /// Size of the partition.
+    size: usize,
+}
+
+#[cfg(target_arch = "xtensa")]
+impl AppStorage<FlashStorage> {
+    /// Uses the [`PARTITION_LABEL`] partition of the internal flash.
+    pub fn open() -> Result<Self, StorageError> {
+        Self::from_partition_table(FlashStorage::new())
+    }
+}
+
+impl<F: NorFlash> AppStorage<F> {
+    /// Uses the `size` bytes of `flash` at `offset`, of which the first
+    /// [`FILESYSTEM_SIZE`] hold the filesystem.
+    pub fn new(flash: F, offset: u32, size: usize) -> Result<Self, GeometryError> {
+        const {
+            assert!(geometry::READ_SIZE % F::READ_SIZE == 0);
+            assert!(geometry::WRITE_SIZE % F::WRITE_SIZE == 0);
+            assert!(geometry::BLOCK_SIZE % F::ERASE_SIZE == 0);
+        }
+        if offset as usize % F::ERASE_SIZE != 0 {
+            return Err(GeometryError::Misaligned {
+                offset,
+                erase_size: F::ERASE_SIZE,
+            });
+        }
+        if size < FILESYSTEM_SIZE {
+            return Err(GeometryError::TooSmall { size });
+        }
+        let capacity = flash.capacity();
+        if offset as usize + size > capacity {
+            return Err(GeometryError::OutOfBounds { capacity });
+        }
+        Ok(Self {
+            flash,
+            offset,
+            size,
+        })
+    }
+
+    /// Uses the [`PARTITION_LABEL`] partition from the partition table on
+    /// `flash`, which must be a data partition with the littlefs subtype.
+    pub fn from_partition_table(mut flash: F) -> Result<Self, StorageError> {
+        let partition =
+            partition::find(&mut flash, PARTITION_LABEL)?.ok_or(StorageError::NotFound)?;
+        if (partition.kind, partition.subtype) != (TYPE_DATA, SUBTYPE_LITTLEFS) {
+            return Err(StorageError::WrongType {
+                kind: partition.kind,
+                subtype: partition.subtype,
+            });
+        }
+        Ok(Self::new(flash, partition.offset, partition.size as usize)?)
+    }
+
+    /// Gives the flash back, e.g. to inspect it after a test.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Returns the flash address of `off` if `len` bytes from there are
+    /// inside the partition and, for erases, cover whole sectors.
+    fn guard(&self, access: Access, off: usize, len: usize) -> Result<u32, Error> {
+        let in_bounds = off.checked_add(len).is_some_and(|end| end <= self.size);
+        let aligned = match access {
+            Access::Erase => off % F::ERASE_SIZE == 0 && len % F::ERASE_SIZE == 0,
+            Access::Read | Access::Write => true,
+        };
+        if in_bounds && aligned {
+            return Ok(self.offset + off as u32);
+        }
+        let violations = GUARD_VIOLATIONS.fetch_add(1, Ordering::Relaxed) + 1;
+        let reason = if in_bounds {
+            "is not aligned to the erase size"
+        } else {
+            "is outside the partition"
+        };
+        error!(
+            "Refused flash {access:?} of {len} bytes at {off:#x} of the {} byte partition at \
+            {:#x}: it {reason}. {violations} refused access(es) since boot",
+            self.size, self.offset
+        );
+        Err(Error::IO)
+    }
+}
+
+impl<F: NorFlash> littlefs2::driver::Storage for AppStorage<F> {
+    const READ_SIZE: usize = geometry::READ_SIZE;
+    const WRITE_SIZE: usize = geometry::WRITE_SIZE;
+    const BLOCK_SIZE: usize = geometry::BLOCK_SIZE;
+    const BLOCK_COUNT: usize = geometry::BLOCK_COUNT;
+
+    type CACHE_SIZE = geometry::CacheSize;
+    type LOOKAHEAD_SIZE = geometry::LookaheadSize;
+
+    fn read(&mut self, off: usize, buf: &mut [u8]) -> Result<usize, Error> {
+        let addr = self.guard(Access::Read, off, buf.len())?;
+        let result = self.flash.read(addr, buf);
+        health::record(Operation::Read, off, buf.len(), &result);
+        result.map(|_| buf.len()).map_err(|e| {
+            error!("Flash read error: {e:?}");
+            Error::IO
+        })
+    }
+
+    fn write(&mut self, off: usize, data: &[u8]) -> Result<usize, Error> {
+        let addr = self.guard(Access::Write, off, data.len())?;
+        let result = self.flash.write(addr, data);
+        health::record(Operation::Write, off, data.len(), &result);
+        result.map(|_| data.len()).map_err(|e| {
+            error!("Flash write error: {e:?}");
+            Error::IO
+        })
+    }
+
+    fn erase(&mut self, off: usize, len: usize) -> Result<usize, Error> {
+        let addr = self.guard(Access::Erase, off, len)?;
+        let result = self.flash.erase(addr, addr + len as u32);
+        health::record(Operation::Erase, off, len, &result);
+        result.map(|_| len).map_err(|e| {
+            error!("Flash erase error: {e:?}");
+            Error::IO
+        })
+    }
+}
+
+#[cfg(target_arch = "xtensa")]
+static ALLOC: StaticCell<Allocation<AppStorage<FlashStorage>>> = StaticCell::new();
+#[cfg(target_arch = "xtensa")]
+static STORAGE: StaticCell<AppStorage<FlashStorage>> = StaticCell::new();
+#[cfg(target_arch = "xtensa")]
+static FILESYSTEM: StaticCell<Filesystem<'static, AppStorage<FlashStorage>>> = StaticCell::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use littlefs2::driver::Storage;
+    use ram_flash::{ERASED, RamFlash};
+
+    const SECTOR: usize = RamFlash::ERASE_SIZE;
+    const OFFSET: u32 = SECTOR as u32;
+
+    /// The filesystem in the middle of a flash, with a sector before and
+    /// after it that the guard must keep it out of.
+    fn storage() -> AppStorage<RamFlash> {
+        let flash = RamFlash::new(FILESYSTEM_SIZE + 2 * SECTOR);
+        AppStorage::new(flash, OFFSET, FILESYSTEM_SIZE).unwrap()
+    }
+
+    /// Asserts that `access` is refused and counted.
+    fn assert_refused(access: impl FnOnce() -> Result<usize, Error>) {
+        let before = guard_violations();
+        assert_eq!(access(), Err(Error::IO));
+        assert!(guard_violations() > before);
+    }
+
+    #[test]
+    fn accesses_inside_the_partition_reach_the_flash() {
+        let mut storage = storage();
+        let end = FILESYSTEM_SIZE - SECTOR;
+        assert_eq!(storage.erase(end, SECTOR), Ok(SECTOR));
+        assert_eq!(storage.write(end, &[0; SECTOR]), Ok(SECTOR));
+        let mut buf = [ERASED; SECTOR];
+        assert_eq!(storage.read(end, &mut buf), Ok(SECTOR));
+        assert_eq!(buf, [0; SECTOR]);
+
+        let flash = storage.into_inner();
+        let written = OFFSET as usize + end;
+        assert!(flash.as_bytes()[written..written + SECTOR] == [0; SECTOR]);
+    }
+
+    #[test]
+    fn accesses_past_the_partition_are_refused() {
+        let mut storage = storage();
+        let mut buf = [0; 4];
+        assert_refused(|| storage.read(FILESYSTEM_SIZE, &mut buf));
+        assert_refused(|| storage.read(FILESYSTEM_SIZE - 2, &mut buf));
+        assert_refused(|| storage.write(FILESYSTEM_SIZE, &buf));
+        assert_refused(|| storage.erase(FILESYSTEM_SIZE, SECTOR));
+        assert_refused(|| storage.erase(0, FILESYSTEM_SIZE + SECTOR));
+        assert!(storage.into_inner().as_bytes().iter().all(|&b| b == ERASED));
+    }
+
+    #[test]
+    fn overflowing_lengths_are_refused() {
+        let mut storage = storage();
+        let buf = [0; 4];
+        assert_refused(|| storage.write(usize::MAX - 1, &buf));
+        assert_refused(|| storage.erase(SECTOR, usize::MAX - SECTOR + 1));
+    }
+
+    #[test]
+    fn erases_of_partial_sectors_are_refused() {
+        let mut storage = storage();
+        assert_refused(|| storage.erase(SECTOR / 2, SECTOR));
+        assert_refused(|| storage.erase(0, SECTOR / 2));
+        assert_refused(|| storage.erase(0, SECTOR + 4));
+    }
+}
@@ -0,0 +1,266 @@
+use embedded_storage::nor_flash::{NorFlashError, NorFlashErrorKind, ReadNorFlash};
+use heapless::String;
+
+/// Where the ESP-IDF bootloader reads the partition table from.
+pub const PARTITION_TABLE_OFFSET: u32 = 0x8000;
+/// The table fills at most this much of its flash sector.
+const PARTITION_TABLE_MAX_LEN: u32 = 0xc00;
+const ENTRY_LEN: usize = 32;
+pub const LABEL_MAX_LEN: usize = 16;
+
+const ENTRY_MAGIC: [u8; 2] = [0xaa, 0x50];
+/// Marks the MD5 checksum of the preceding entries.
+const MD5_MAGIC: [u8; 2] = [0xeb, 0xeb];
+/// Erased flash after the last entry.
+const END_MAGIC: [u8; 2] = [0xff, 0xff];
+
+pub const TYPE_APP: u8 = 0x00;
+pub const TYPE_DATA: u8 = 0x01;
+pub const SUBTYPE_LITTLEFS: u8 = 0x83;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to read partition table: {0:?}")]
+    Read(NorFlashErrorKind),
+    #[error("Invalid partition table entry at {offset:#x}")]
+    InvalidEntry { offset: u32 },
+}
+
+/// An entry of the partition table, as written by `gen_esp32part.py` from
+/// `partitions.csv`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// [`TYPE_APP`], [`TYPE_DATA`] or a custom type.
+    pub kind: u8,
+    pub subtype: u8,
+    pub offset: u32,
+    pub size: u32,
+    pub label: String<LABEL_MAX_LEN>,
+    pub flags: u32,
+}
+
+impl Partition {
+    fn parse(entry: &[u8; ENTRY_LEN]) -> Option<Self> {
+        let u32_at = |i: usize| u32::from_le_bytes(entry[i..i + 4].try_into().unwrap());
+        let label = &entry[12..28];
+        let label_len = label.iter().position(|&b| b == 0).unwrap_or(label.len());
+        let label = core::str::from_utf8(&label[..label_len]).ok()?;
+        Some(Self {
+            kind: entry[2],
+            subtype: entry[3],
+            offset: u32_at(4),
+            size: u32_at(8),
+            label: String::try_from(label).ok()?,
+            flags: u32_at(28),
+        })
+    }
+}
+
+/// Iterates over the entries of the partition table on `flash`.
+///
+/// The MD5 checksum at the end of the table is skipped, not verified. An
+/// entry that is not a partition stops the iteration with an error.
+pub fn partitions<F: ReadNorFlash>(flash: &mut F) -> Partitions<'_, F> {
+    Partitions {
+        flash,
+        offset: PARTITION_TABLE_OFFSET,
+    }
+}
+
+/// Finds the partition labelled `label`.
+pub fn find<F: ReadNorFlash>(flash: &mut F, label: &str) -> Result<Option<Partition>, Error> {
+    partitions(flash)
+        .find(|partition| !matches!(partition, Ok(p) if p.label != label))
+        .transpose()
+}
+
+pub struct Partitions<'a, F> {
+    flash: &'a mut F,
+    offset: u32,
+}
+
+impl<F: ReadNorFlash> Iterator for Partitions<'_, F> {
+    type Item = Result<Partition, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.offset < PARTITION_TABLE_OFFSET + PARTITION_TABLE_MAX_LEN {
+            let offset = self.offset;
+            let mut entry = [0; ENTRY_LEN];
+            if let Err(e) = self.flash.read(offset, &mut entry) {
+                self.offset = u32::MAX;
+                return Some(Err(Error::Read(e.kind())));
+            }
+            self.offset += ENTRY_LEN as u32;
+            match [entry[0], entry[1]] {
+                ENTRY_MAGIC => {
+                    return Some(Partition::parse(&entry).ok_or(Error::InvalidEntry { offset }));
+                }
+                MD5_MAGIC => continue,
+                END_MAGIC => break,
+                _ => {
+                    self.offset = u32::MAX;
+                    return Some(Err(Error::InvalidEntry { offset }));
+                }
+            }
+        }
+        self.offset = u32::MAX;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::ram_flash::{ERASED, RamFlash};
+    use crate::filesystem::{AppStorage, FILESYSTEM_SIZE, GeometryError, StorageError};
+    use alloc::vec::Vec;
+
+    const SUBTYPE_FACTORY: u8 = 0x00;
+    const SUBTYPE_NVS: u8 = 0x02;
+    const STORAGE_OFFSET: u32 = 0x20000;
+    const CAPACITY: usize = STORAGE_OFFSET as usize + FILESYSTEM_SIZE;
+
+    fn entry(kind: u8, subtype: u8, offset: u32, size: usize, label: &str) -> [u8; ENTRY_LEN] {
+        let mut entry = [0; ENTRY_LEN];
+        entry[..2].copy_from_slice(&ENTRY_MAGIC);
+        entry[2] = kind;
+        entry[3] = subtype;
+        entry[4..8].copy_from_slice(&offset.to_le_bytes());
+        entry[8..12].copy_from_slice(&(size as u32).to_le_bytes());
+        entry[12..12 + label.len()].copy_from_slice(label.as_bytes());
+        entry
+    }
+
+    /// The checksum entry `gen_esp32part.py` appends, with a made-up sum.
+    fn md5_entry() -> [u8; ENTRY_LEN] {
+        let mut entry = [ERASED; ENTRY_LEN];
+        entry[..2].copy_from_slice(&MD5_MAGIC);
+        entry[16..].fill(0x5a);
+        entry
+    }
+
+    fn app() -> [u8; ENTRY_LEN] {
+        entry(TYPE_APP, SUBTYPE_FACTORY, 0x10000, 0x10000, "factory")
+    }
+
+    fn storage(subtype: u8, size: usize) -> [u8; ENTRY_LEN] {
+        entry(TYPE_DATA, subtype, STORAGE_OFFSET, size, "storage")
+    }
+
+    /// An erased flash with `entries` as its partition table.
+    fn with_table(entries: &[[u8; ENTRY_LEN]]) -> RamFlash {
+        let mut data = alloc::vec![ERASED; CAPACITY];
+        let table = &mut data[PARTITION_TABLE_OFFSET as usize..];
+        for (i, entry) in entries.iter().enumerate() {
+            table[i * ENTRY_LEN..][..ENTRY_LEN].copy_from_slice(entry);
+        }
+        RamFlash::from_bytes(data)
+    }
+
+    fn labels(flash: &mut RamFlash) -> Result<Vec<String<LABEL_MAX_LEN>>, Error> {
+        partitions(flash).map(|p| p.map(|p| p.label)).collect()
+    }
+
+    #[test]
+    fn entries_are_read_up_to_the_end_marker() {
+        let storage = storage(SUBTYPE_LITTLEFS, FILESYSTEM_SIZE);
+        // after the end marker, so never read
+        let nvs = entry(TYPE_DATA, SUBTYPE_NVS, 0x9000, 0x6000, "nvs");
+        let mut end = [ERASED; ENTRY_LEN];
+        end[2..].fill(0);
+        let mut flash = with_table(&[app(), storage, md5_entry(), end, nvs]);
+
+        assert_eq!(labels(&mut flash).unwrap(), ["factory", "storage"]);
+        let partition = find(&mut flash, "storage").unwrap().unwrap();
+        assert_eq!(
+            partition,
+            Partition {
+                kind: TYPE_DATA,
+                subtype: SUBTYPE_LITTLEFS,
+                offset: STORAGE_OFFSET,
+                size: FILESYSTEM_SIZE as u32,
+                label: String::try_from("storage").unwrap(),
+                flags: 0,
+            }
+        );
+        assert_eq!(find(&mut flash, "nvs"), Ok(None));
+        assert_eq!(find(&mut flash, "stor"), Ok(None));
+    }
+
+    #[test]
+    fn an_empty_table_has_no_partitions() {
+        let mut flash = with_table(&[]);
+        assert_eq!(labels(&mut flash).unwrap().len(), 0);
+        assert_eq!(
+            AppStorage::from_partition_table(flash).err(),
+            Some(StorageError::NotFound)
+        );
+    }
+
+    #[test]
+    fn garbage_stops_the_iteration() {
+        let mut garbage = app();
+        garbage[..2].copy_from_slice(&[0x12, 0x34]);
+        let storage = storage(SUBTYPE_LITTLEFS, FILESYSTEM_SIZE);
+        let mut flash = with_table(&[app(), garbage, storage]);
+        let offset = PARTITION_TABLE_OFFSET + ENTRY_LEN as u32;
+
+        let mut iter = partitions(&mut flash);
+        assert_eq!(iter.next().unwrap().unwrap().label, "factory");
+        assert_eq!(iter.next(), Some(Err(Error::InvalidEntry { offset })));
+        assert_eq!(iter.next(), None);
+        assert_eq!(
+            find(&mut flash, "storage"),
+            Err(Error::InvalidEntry { offset })
+        );
+        assert_eq!(
+            AppStorage::from_partition_table(flash).err(),
+            Some(StorageError::PartitionTable(Error::InvalidEntry { offset }))
+        );
+    }
+
+    #[test]
+    fn labels_must_be_utf8() {
+        let mut invalid = app();
+        invalid[12] = 0xff;
+        let mut flash = with_table(&[invalid]);
+        assert_eq!(
+            labels(&mut flash),
+            Err(Error::InvalidEntry {
+                offset: PARTITION_TABLE_OFFSET
+            })
+        );
+    }
+
+    #[test]
+    fn a_table_beyond_the_flash_fails_to_read() {
+        let mut flash = RamFlash::new(PARTITION_TABLE_OFFSET as usize);
+        assert_eq!(
+            labels(&mut flash),
+            Err(Error::Read(NorFlashErrorKind::OutOfBounds))
+        );
+    }
+
+    #[test]
+    fn the_storage_partition_must_be_a_large_enough_littlefs_partition() {
+        let flash = with_table(&[app(), storage(SUBTYPE_NVS, FILESYSTEM_SIZE)]);
+        assert_eq!(
+            AppStorage::from_partition_table(flash).err(),
+            Some(StorageError::WrongType {
+                kind: TYPE_DATA,
+                subtype: SUBTYPE_NVS
+            })
+        );
+
+        let size = FILESYSTEM_SIZE - 0x1000;
+        let flash = with_table(&[app(), storage(SUBTYPE_LITTLEFS, size)]);
+        assert_eq!(
+            AppStorage::from_partition_table(flash).err(),
+            Some(StorageError::Geometry(GeometryError::TooSmall { size }))
+        );
+
+        let flash = with_table(&[app(), storage(SUBTYPE_LITTLEFS, FILESYSTEM_SIZE)]);
+        let storage = AppStorage::from_partition_table(flash).unwrap();
+        assert_eq!(storage.offset, STORAGE_OFFSET);
+    }
+}
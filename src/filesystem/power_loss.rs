@@ -0,0 +1,184 @@
+use alloc::vec;
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// A NOR flash that loses power in the middle of a chosen program or erase,
+/// for testing that littlefs and its users survive brown-outs.
+///
+/// The interrupted operation is torn: a program lands on a random prefix of
+/// its words and clears only some bits of the word after it, an erase resets
+/// a random prefix of its sectors and only some bits of the sector after it.
+/// Every access fails from then on until [`Self::power_on`]. What lands is
+/// decided by a seed, so a failure can be replayed.
+pub struct PowerLossFlash<F> {
+    flash: F,
+    /// Programs and erases left before the power is cut.
+    remaining: Option<u32>,
+    /// Programs and erases started since the last [`Self::power_on`].
+    operations: u32,
+    powered: bool,
+    rng: XorShift,
+}
+
+impl<F: NorFlash> PowerLossFlash<F> {
+    /// Wraps `flash` without a power cut scheduled.
+    pub fn new(flash: F, seed: u32) -> Self {
+        Self {
+            flash,
+            remaining: None,
+            operations: 0,
+            powered: true,
+            rng: XorShift::new(seed),
+        }
+    }
+
+    /// Cuts the power during the program or erase after the next `ops`.
+    pub fn cut_after(&mut self, ops: u32) {
+        self.remaining = Some(ops);
+    }
+
+    pub fn is_powered(&self) -> bool {
+        self.powered
+    }
+
+    /// Programs and erases started since the last power on, including the
+    /// one that was cut.
+    pub fn operations(&self) -> u32 {
+        self.operations
+    }
+
+    /// Restores power, without a cut scheduled.
+    pub fn power_on(&mut self) {
+        self.powered = true;
+        self.remaining = None;
+        self.operations = 0;
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Counts a program or erase. Returns `true` if the power goes during
+    /// this one.
+    fn start_operation(&mut self) -> Result<bool, NorFlashErrorKind> {
+        if !self.powered {
+            return Err(NorFlashErrorKind::Other);
+        }
+        self.operations += 1;
+        match &mut self.remaining {
+            Some(0) => {
+                self.powered = false;
+                Ok(true)
+            }
+            Some(remaining) => {
+                *remaining -= 1;
+                Ok(false)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn torn_write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+        let words = bytes.len() / F::WRITE_SIZE;
+        let landed = self.rng.below(words as u32 + 1) as usize * F::WRITE_SIZE;
+        self.write_inner(offset, &bytes[..landed])?;
+        if landed < bytes.len() {
+            // some of the bits that should be cleared still are set
+            let word = landed..landed + F::WRITE_SIZE;
+            let mut partial = vec![0; F::WRITE_SIZE];
+            self.read_inner(offset + landed as u32, &mut partial)?;
+            for (old, &new) in partial.iter_mut().zip(&bytes[word]) {
+                *old = new | (self.rng.next_u32() as u8 & *old);
+            }
+            self.write_inner(offset + landed as u32, &partial)?;
+        }
+        Err(NorFlashErrorKind::Other)
+    }
+
+    fn torn_erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
+        let sectors = (to - from) / F::ERASE_SIZE as u32;
+        let landed = from + self.rng.below(sectors + 1) * F::ERASE_SIZE as u32;
+        self.flash.erase(from, landed).map_err(|e| e.kind())?;
+        if landed < to {
+            // some of the bits are set, the rest keeps its old value
+            let mut sector = vec![0; F::ERASE_SIZE];
+            self.read_inner(landed, &mut sector)?;
+            for byte in sector.iter_mut() {
+                *byte |= self.rng.next_u32() as u8;
+            }
+            let end = landed + F::ERASE_SIZE as u32;
+            self.flash.erase(landed, end).map_err(|e| e.kind())?;
+            self.write_inner(landed, &sector)?;
+        }
+        Err(NorFlashErrorKind::Other)
+    }
+
+    fn read_inner(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind> {
+        self.flash.read(offset, bytes).map_err(|e| e.kind())
+    }
+
+    fn write_inner(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+        self.flash.write(offset, bytes).map_err(|e| e.kind())
+    }
+}
+
+impl<F> ErrorType for PowerLossFlash<F> {
+    type Error = NorFlashErrorKind;
+}
+
+impl<F: NorFlash> ReadNorFlash for PowerLossFlash<F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        if !self.powered {
+            return Err(NorFlashErrorKind::Other);
+        }
+        self.read_inner(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl<F: NorFlash> NorFlash for PowerLossFlash<F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        match self.start_operation()? {
+            true => self.torn_erase(from, to),
+            false => self.flash.erase(from, to).map_err(|e| e.kind()),
+        }
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        match self.start_operation()? {
+            true => self.torn_write(offset, bytes),
+            false => self.write_inner(offset, bytes),
+        }
+    }
+}
+
+/// Marsaglia's xorshift32.
+struct XorShift(u32);
+
+impl XorShift {
+    fn new(seed: u32) -> Self {
+        // zero is a fixed point
+        Self(seed.max(1))
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// A number in `0..n`.
+    fn below(&mut self, n: u32) -> u32 {
+        self.next_u32() % n
+    }
+}
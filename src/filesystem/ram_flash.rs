@@ -0,0 +1,97 @@
+use super::{AppStorage, FILESYSTEM_SIZE};
+use alloc::vec;
+use alloc::vec::Vec;
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash, check_erase, check_read, check_write,
+};
+use littlefs2::fs::Filesystem;
+
+/// The value of an erased byte.
+pub const ERASED: u8 = 0xff;
+
+/// A NOR flash in RAM, for running the filesystem in the host tests.
+///
+/// It has the geometry of the ESP32 internal flash. Like real NOR flash,
+/// erasing sets every bit and writing can only clear bits. Unlike real flash,
+/// a write that would have to set a bit fails instead of silently storing
+/// the AND of old and new data, so a missing erase is caught where it
+/// happens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RamFlash {
+    data: Vec<u8>,
+}
+
+impl RamFlash {
+    /// An erased flash of `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            data: vec![ERASED; capacity],
+        }
+    }
+
+    /// A flash holding `data`, e.g. an image read from a device.
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl ErrorType for RamFlash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let start = offset as usize;
+        bytes.copy_from_slice(&self.data[start..start + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = 4096;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        self.data[from as usize..to as usize].fill(ERASED);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        let start = offset as usize;
+        let target = &mut self.data[start..start + bytes.len()];
+        if target
+            .iter()
+            .zip(bytes)
+            .any(|(&old, &new)| old & new != new)
+        {
+            return Err(NorFlashErrorKind::Other);
+        }
+        target.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// The filesystem on an erased [`RamFlash`] that holds nothing else.
+pub fn blank_storage() -> AppStorage<RamFlash> {
+    AppStorage::new(RamFlash::new(FILESYSTEM_SIZE), 0, FILESYSTEM_SIZE).unwrap()
+}
+
+/// Like [`blank_storage`], but formatted.
+pub fn formatted_storage() -> AppStorage<RamFlash> {
+    let mut storage = blank_storage();
+    Filesystem::format(&mut storage).unwrap();
+    storage
+}
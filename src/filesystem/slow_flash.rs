@@ -0,0 +1,131 @@
+use core::cell::Cell;
+use embassy_time::{Duration, block_for};
+use embedded_storage::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+use std::sync::Mutex;
+
+/// How long a NOR flash is busy with each operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    /// Per started 256 byte page.
+    pub program_page: Duration,
+    /// Per sector.
+    pub erase_sector: Duration,
+}
+
+impl Timing {
+    /// Typical timing of the SPI flash of ESP32 modules.
+    pub const TYPICAL: Self = Self {
+        program_page: Duration::from_micros(700),
+        erase_sector: Duration::from_millis(45),
+    };
+
+    /// Worst case timing from the datasheets of the same flashes, reached as
+    /// they wear.
+    pub const WORST_CASE: Self = Self {
+        program_page: Duration::from_millis(3),
+        erase_sector: Duration::from_millis(400),
+    };
+}
+
+const PAGE_SIZE: usize = 256;
+
+/// Operations started or finished on any [`SlowFlash`], odd while one is
+/// busy, and how long the busy one takes.
+static OPERATIONS: Mutex<(u32, Duration)> = Mutex::new((0, Duration::from_ticks(0)));
+
+std::thread_local! {
+    /// Busy time of the operations run on this thread.
+    static BUSY_TIME: Cell<Duration> = const { Cell::new(Duration::from_ticks(0)) };
+}
+
+/// Waits for the flash operation running on another thread, if any, like
+/// the other core of the ESP32 does: the flash disables the cache it runs
+/// its code from until the operation is done.
+///
+/// Call before every poll of an executor standing in for that core. Returns
+/// the busy time of the operation it waited for, or zero.
+pub fn wait_for_flash() -> Duration {
+    let (operations, duration) = *OPERATIONS.lock().unwrap();
+    if operations % 2 == 0 {
+        return Duration::from_ticks(0);
+    }
+    while OPERATIONS.lock().unwrap().0 == operations {
+        std::thread::yield_now();
+    }
+    duration
+}
+
+/// The total busy time of the flash operations this thread ran, which
+/// stalled it for that long.
+pub fn busy_time() -> Duration {
+    BUSY_TIME.get()
+}
+
+/// A NOR flash that blocks the caller for as long as a real flash would,
+/// for measuring how long flash operations stall the executors of both
+/// cores, see [`wait_for_flash`] and [`busy_time`].
+///
+/// Reads are not slowed down, as they are cached.
+pub struct SlowFlash<F> {
+    flash: F,
+    timing: Timing,
+}
+
+impl<F: NorFlash> SlowFlash<F> {
+    pub fn new(flash: F, timing: Timing) -> Self {
+        Self { flash, timing }
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+}
+
+impl<F: ErrorType> ErrorType for SlowFlash<F> {
+    type Error = F::Error;
+}
+
+impl<F: NorFlash> ReadNorFlash for SlowFlash<F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl<F: NorFlash> NorFlash for SlowFlash<F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let sectors = (to - from) / F::ERASE_SIZE as u32;
+        busy(self.timing.erase_sector * sectors, || {
+            self.flash.erase(from, to)
+        })
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let first = offset as usize / PAGE_SIZE;
+        let last = (offset as usize + bytes.len()).div_ceil(PAGE_SIZE);
+        busy(self.timing.program_page * (last - first) as u32, || {
+            self.flash.write(offset, bytes)
+        })
+    }
+}
+
+/// Runs `operation` after blocking for `duration`, as one busy period for
+/// [`wait_for_flash`] and [`busy_time`].
+fn busy<R>(duration: Duration, operation: impl FnOnce() -> R) -> R {
+    let mut operations = OPERATIONS.lock().unwrap();
+    *operations = (operations.0 + 1, duration);
+    drop(operations);
+    block_for(duration);
+    let result = operation();
+    OPERATIONS.lock().unwrap().0 += 1;
+    BUSY_TIME.set(BUSY_TIME.get() + duration);
+    result
+}
@@ -0,0 +1,124 @@
+//! Measures how long the writes of [`FsHandle`] stall the executor of the
+//! caller, with and without a worker on another core.
+//!
+//! Stalls are measured in the busy time of the [`SlowFlash`] model, not on
+//! the wall clock, so host scheduling does not change the result.
+
+use super::ram_flash::RamFlash;
+use super::slow_flash::{SlowFlash, Timing, busy_time, wait_for_flash};
+use super::{AppStorage, FILESYSTEM_SIZE, FsHandle};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec;
+use core::cell::Cell;
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+use embassy_futures::select::select;
+use embassy_futures::yield_now;
+use embassy_time::{Duration, Timer};
+use littlefs2::fs::{Allocation, Filesystem};
+use std::thread::{self, Thread};
+
+const TIMING: Timing = Timing::TYPICAL;
+/// Writes of this size erase several blocks in one littlefs operation.
+const FILE_SIZE: usize = 16 * 1024;
+const WRITES: usize = 3;
+
+/// With the writes on a worker, the main core waits for one flash
+/// operation at a time, at most a sector erase. Without, it waits for whole
+/// littlefs operations.
+#[test]
+fn worker_bounds_the_stall_to_one_flash_operation() {
+    let fs = mount();
+
+    let inline = worst_stall(fs);
+    assert!(
+        inline >= TIMING.erase_sector * 4,
+        "writing {FILE_SIZE} bytes stalled the caller for only {inline}"
+    );
+
+    thread::spawn(|| block_on(fs.serve(), || ()));
+    block_on(
+        async {
+            // the first jobs may still run inline
+            while fs.run(|_| thread::current().id()).await == thread::current().id() {
+                thread::yield_now();
+            }
+        },
+        || (),
+    );
+    let offloaded = worst_stall(fs);
+    assert!(
+        offloaded <= TIMING.erase_sector,
+        "writes on the worker stalled the main core for {offloaded}"
+    );
+}
+
+/// The longest the main core could not poll its tasks while writing
+/// [`WRITES`] files through `fs`: the flash operations a poll ran itself,
+/// plus the one on the other core it had to wait for before the next poll.
+fn worst_stall(fs: &FsHandle) -> Duration {
+    let contents = vec![0x55; FILE_SIZE];
+    let writes = async {
+        for _ in 0..WRITES {
+            fs.write(littlefs2::path!("/data"), &contents)
+                .await
+                .unwrap();
+            // ends the poll, as inline writes never wait
+            yield_now().await;
+        }
+    };
+    // other tasks, e.g. the Wi-Fi driver, keep the main core polling
+    let other_tasks = async {
+        loop {
+            Timer::after_millis(1).await;
+        }
+    };
+    let worst = Cell::new(Duration::from_ticks(0));
+    let busy_at_poll = Cell::new(busy_time());
+    // the main core runs from flash, so it waits for every flash operation
+    block_on(select(writes, other_tasks), || {
+        // what the previous poll ran, and what it waits for now
+        let stall = busy_time() - busy_at_poll.get() + wait_for_flash();
+        worst.set(worst.get().max(stall));
+        busy_at_poll.set(busy_time());
+    });
+    worst.get().max(busy_time() - busy_at_poll.get())
+}
+
+/// A formatted filesystem on a [`SlowFlash`], for the rest of the tests.
+fn mount() -> &'static FsHandle {
+    let flash = SlowFlash::new(RamFlash::new(FILESYSTEM_SIZE), TIMING);
+    let storage = Box::leak(Box::new(
+        AppStorage::new(flash, 0, FILESYSTEM_SIZE).unwrap(),
+    ));
+    Filesystem::format(storage).unwrap();
+    let alloc = Box::leak(Box::new(Allocation::new()));
+    let fs = Box::leak(Box::new(Filesystem::mount(alloc, storage).unwrap()));
+    Box::leak(Box::new(FsHandle::new(fs)))
+}
+
+/// Runs `future` on this thread, a core of its own, calling `before_poll`
+/// before every poll.
+fn block_on<F: Future>(future: F, before_poll: impl Fn()) -> F::Output {
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        before_poll();
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
@@ -0,0 +1,538 @@
+use crate::filesystem::{self, Unavailable, read_file, replace_file};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use littlefs2::io::Error;
+use littlefs2::object_safe::DynFilesystem;
+use littlefs2::path::{Path, PathBuf};
+use log::{info, warn};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Directory holding one subdirectory per namespace.
+const KV_DIR: &Path = littlefs2::path!("/kv");
+/// Maximum length of namespace and key names, as in ESP-IDF NVS.
+pub const NAME_MAX_LEN: usize = 15;
+/// Maximum size of an encoded value, including its one byte type tag, so a
+/// string or blob can have up to `VALUE_MAX_LEN - 1` bytes.
+pub const VALUE_MAX_LEN: usize = 4096;
+/// Below this many free blocks, writes compact the store first and fail if
+/// that does not help, so that the config can still be written.
+pub const LOW_SPACE_BLOCKS: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum KvError {
+    #[error("Names must be 1 to {NAME_MAX_LEN} ASCII letters, digits, `_` or `-`")]
+    InvalidName,
+    #[error(transparent)]
+    Unavailable(#[from] Unavailable),
+    #[error("Filesystem error: {}", .0.code())]
+    Io(Error),
+    #[error("Value is {found:?}, not {expected:?}")]
+    TypeMismatch {
+        expected: ValueType,
+        found: ValueType,
+    },
+    #[error("Stored value is corrupt")]
+    Corrupt,
+    #[error("Failed to encode value: {0}")]
+    Encode(postcard::Error),
+    #[error("Failed to decode value: {0}")]
+    Decode(postcard::Error),
+    #[error("Encoded value, with its type tag, is larger than {VALUE_MAX_LEN} bytes")]
+    TooLarge,
+    #[error("Fewer than {LOW_SPACE_BLOCKS} free blocks left")]
+    NoSpace,
+}
+
+/// The type a value was stored with. Values can only be read back as the
+/// same type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ValueType {
+    I64 = 1,
+    U64 = 2,
+    Str = 3,
+    Blob = 4,
+    /// A serde value, postcard-encoded.
+    Serde = 5,
+}
+
+impl ValueType {
+    fn from_tag(tag: u8) -> Option<Self> {
+        [Self::I64, Self::U64, Self::Str, Self::Blob, Self::Serde]
+            .into_iter()
+            .find(|ty| *ty as u8 == tag)
+    }
+}
+
+/// A group of typed values on the shared filesystem, like an ESP-IDF NVS
+/// namespace.
+///
+/// Each value is a file `/kv/<namespace>/<key>` holding a type tag and the
+/// encoded value. Writes replace the file atomically, so a power loss leaves
+/// either the old or the new value, and writing an unchanged value does not
+/// touch the flash.
+#[derive(Debug, Clone)]
+pub struct Namespace {
+    name: String,
+}
+
+impl Namespace {
+    pub fn new(name: &str) -> Result<Self, KvError> {
+        check_name(name)?;
+        Ok(Self {
+            name: name.to_string(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub async fn get_i64(&self, key: &str) -> Result<Option<i64>, KvError> {
+        self.get_postcard(key, ValueType::I64).await
+    }
+
+    pub async fn set_i64(&self, key: &str, value: i64) -> Result<(), KvError> {
+        self.set_postcard(key, ValueType::I64, &value).await
+    }
+
+    pub async fn get_u64(&self, key: &str) -> Result<Option<u64>, KvError> {
+        self.get_postcard(key, ValueType::U64).await
+    }
+
+    pub async fn set_u64(&self, key: &str, value: u64) -> Result<(), KvError> {
+        self.set_postcard(key, ValueType::U64, &value).await
+    }
+
+    pub async fn get_str(&self, key: &str) -> Result<Option<String>, KvError> {
+        match self.get_raw(key, ValueType::Str).await? {
+            Some(data) => String::from_utf8(data)
+                .map(Some)
+                .map_err(|_| KvError::Corrupt),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set_str(&self, key: &str, value: &str) -> Result<(), KvError> {
+        self.set_raw(key, ValueType::Str, value.as_bytes()).await
+    }
+
+    pub async fn get_blob(&self, key: &str) -> Result<Option<Vec<u8>>, KvError> {
+        self.get_raw(key, ValueType::Blob).await
+    }
+
+    pub async fn set_blob(&self, key: &str, value: &[u8]) -> Result<(), KvError> {
+        self.set_raw(key, ValueType::Blob, value).await
+    }
+
+    /// Reads a value stored by [`Self::set`].
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, KvError> {
+        self.get_postcard(key, ValueType::Serde).await
+    }
+
+    /// Stores any serde value, postcard-encoded. Its schema is not stored,
+    /// so changing the type breaks reading older values.
+    pub async fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<(), KvError> {
+        self.set_postcard(key, ValueType::Serde, value).await
+    }
+
+    /// Removes `key`. Returns whether it existed.
+    pub async fn remove(&self, key: &str) -> Result<bool, KvError> {
+        let path = self.path(key)?;
+        filesystem::handle()?
+            .run(move |fs| remove_value(fs, &path))
+            .await
+    }
+
+    /// The keys in this namespace.
+    pub async fn keys(&self) -> Result<Vec<String>, KvError> {
+        let dir = self.dir()?;
+        filesystem::handle()?.with(|fs| list_keys(fs, &dir)).await
+    }
+
+    /// Removes all keys in this namespace.
+    pub async fn clear(&self) -> Result<(), KvError> {
+        let dir = self.dir()?;
+        filesystem::handle()?
+            .run(move |fs| remove_namespace(fs, &dir))
+            .await
+    }
+
+    async fn get_postcard<T: DeserializeOwned>(
+        &self,
+        key: &str,
+        ty: ValueType,
+    ) -> Result<Option<T>, KvError> {
+        match self.get_raw(key, ty).await? {
+            Some(data) => postcard::from_bytes(&data)
+                .map(Some)
+                .map_err(KvError::Decode),
+            None => Ok(None),
+        }
+    }
+
+    async fn set_postcard<T: Serialize + ?Sized>(
+        &self,
+        key: &str,
+        ty: ValueType,
+        value: &T,
+    ) -> Result<(), KvError> {
+        let data = postcard::to_allocvec(value).map_err(KvError::Encode)?;
+        self.set_raw(key, ty, &data).await
+    }
+
+    /// Reads the value of `key` without its type tag.
+    async fn get_raw(&self, key: &str, ty: ValueType) -> Result<Option<Vec<u8>>, KvError> {
+        let path = self.path(key)?;
+        filesystem::handle()?
+            .with(|fs| read_value(fs, &path, ty))
+            .await
+    }
+
+    async fn set_raw(&self, key: &str, ty: ValueType, value: &[u8]) -> Result<(), KvError> {
+        let path = self.path(key)?;
+        let dir = self.dir()?;
+        let data = encode(ty, value)?;
+        filesystem::handle()?
+            .run(move |fs| write_value(fs, &dir, &path, &data))
+            .await
+    }
+
+    fn dir(&self) -> Result<PathBuf, KvError> {
+        to_path(&format!("{KV_DIR}/{}", self.name))
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, KvError> {
+        check_name(key)?;
+        to_path(&format!("{KV_DIR}/{}/{key}", self.name))
+    }
+}
+
+/// Reads the value file `path` and checks that it holds a `ty`. Returns the
+/// value without its type tag.
+fn read_value(
+    fs: &dyn DynFilesystem,
+    path: &Path,
+    ty: ValueType,
+) -> Result<Option<Vec<u8>>, KvError> {
+    let mut data = match read_file(fs, path, VALUE_MAX_LEN) {
+        Ok(data) => data,
+        Err(e) if e == Error::NO_SUCH_ENTRY => return Ok(None),
+        Err(e) => return Err(KvError::Io(e)),
+    };
+    let found = data
+        .first()
+        .and_then(|&tag| ValueType::from_tag(tag))
+        .ok_or(KvError::Corrupt)?;
+    if found != ty {
+        return Err(KvError::TypeMismatch {
+            expected: ty,
+            found,
+        });
+    }
+    data.remove(0);
+    Ok(Some(data))
+}
+
+/// The contents of a value file: the type tag, then `value`.
+fn encode(ty: ValueType, value: &[u8]) -> Result<Vec<u8>, KvError> {
+    if value.len() + 1 > VALUE_MAX_LEN {
+        return Err(KvError::TooLarge);
+    }
+    let mut data = Vec::with_capacity(value.len() + 1);
+    data.push(ty as u8);
+    data.extend_from_slice(value);
+    Ok(data)
+}
+
+/// Replaces the value file `path` in the namespace directory `dir` by
+/// `data`, unless it already holds `data`.
+fn write_value(
+    fs: &dyn DynFilesystem,
+    dir: &Path,
+    path: &Path,
+    data: &[u8],
+) -> Result<(), KvError> {
+    if read_file(fs, path, VALUE_MAX_LEN).is_ok_and(|old| old == data) {
+        return Ok(());
+    }
+    ensure_space(fs)?;
+    match fs.create_dir_all(dir) {
+        Err(e) if e != Error::ENTRY_ALREADY_EXISTED => return Err(KvError::Io(e)),
+        _ => (),
+    }
+    replace_file(fs, path, data, false).map_err(KvError::Io)
+}
+
+fn remove_value(fs: &dyn DynFilesystem, path: &Path) -> Result<bool, KvError> {
+    match fs.remove(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e == Error::NO_SUCH_ENTRY => Ok(false),
+        Err(e) => Err(KvError::Io(e)),
+    }
+}
+
+fn list_keys(fs: &dyn DynFilesystem, dir: &Path) -> Result<Vec<String>, KvError> {
+    match list_files(fs, dir) {
+        Ok(names) => Ok(names
+            .into_iter()
+            .filter(|n| check_name(n).is_ok())
+            .collect()),
+        Err(e) if e == Error::NO_SUCH_ENTRY => Ok(Vec::new()),
+        Err(e) => Err(KvError::Io(e)),
+    }
+}
+
+fn remove_namespace(fs: &dyn DynFilesystem, dir: &Path) -> Result<(), KvError> {
+    match fs.remove_dir_all(dir) {
+        Err(e) if e != Error::NO_SUCH_ENTRY => Err(KvError::Io(e)),
+        _ => Ok(()),
+    }
+}
+
+/// Removes what interrupted writes left behind and empty namespaces, which
+/// take two blocks each. Returns the number of entries removed.
+///
+/// Runs by itself when a write finds fewer than [`LOW_SPACE_BLOCKS`] free.
+pub async fn compact() -> Result<usize, KvError> {
+    filesystem::handle()?.run(compact_fs).await
+}
+
+fn compact_fs(fs: &dyn DynFilesystem) -> Result<usize, KvError> {
+    let namespaces = match list_files(fs, KV_DIR) {
+        Ok(namespaces) => namespaces,
+        Err(e) if e == Error::NO_SUCH_ENTRY => return Ok(0),
+        Err(e) => return Err(KvError::Io(e)),
+    };
+    let mut removed = 0;
+    for namespace in namespaces {
+        let dir = to_path(&format!("{KV_DIR}/{namespace}"))?;
+        // namespaces are directories, other files are not ours to remove
+        if !fs.metadata(&dir).map_err(KvError::Io)?.is_dir() {
+            continue;
+        }
+        let files = list_files(fs, &dir).map_err(KvError::Io)?;
+        let mut kept = 0;
+        for file in files {
+            // keys cannot contain `.`, so these are temporary files
+            if check_name(&file).is_ok() {
+                kept += 1;
+                continue;
+            }
+            fs.remove(&to_path(&format!("{dir}/{file}"))?)
+                .map_err(KvError::Io)?;
+            removed += 1;
+        }
+        if kept == 0 {
+            fs.remove_dir(&dir).map_err(KvError::Io)?;
+            removed += 1;
+        }
+    }
+    info!("Compacted key-value store: removed {removed} entries");
+    Ok(removed)
+}
+
+/// Makes sure a write leaves at least [`LOW_SPACE_BLOCKS`] free.
+fn ensure_space(fs: &dyn DynFilesystem) -> Result<(), KvError> {
+    let free = || fs.available_blocks().map_err(KvError::Io);
+    if free()? >= LOW_SPACE_BLOCKS {
+        return Ok(());
+    }
+    compact_fs(fs)?;
+    let left = free()?;
+    if left < LOW_SPACE_BLOCKS {
+        warn!("Refusing key-value write with {left} free blocks");
+        return Err(KvError::NoSpace);
+    }
+    Ok(())
+}
+
+/// Names of the entries in the directory `path`, without `.` and `..`.
+fn list_files(fs: &dyn DynFilesystem, path: &Path) -> Result<Vec<String>, Error> {
+    fs.read_dir_and_then(path, &mut |entries| {
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            match entry.file_name().as_str() {
+                "." | ".." => (),
+                name => names.push(name.to_string()),
+            }
+        }
+        Ok(names)
+    })
+}
+
+fn check_name(name: &str) -> Result<(), KvError> {
+    let valid = (1..=NAME_MAX_LEN).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+    valid.then_some(()).ok_or(KvError::InvalidName)
+}
+
+fn to_path(path: &str) -> Result<PathBuf, KvError> {
+    PathBuf::try_from(path).map_err(|_| KvError::InvalidName)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::ram_flash::{RamFlash, formatted_storage};
+    use crate::filesystem::{AppStorage, FILESYSTEM_SIZE};
+    use littlefs2::fs::{FileOpenFlags, Filesystem};
+    use littlefs2::path;
+
+    const DIR: &Path = path!("/kv/test");
+    const KEY: &Path = path!("/kv/test/key");
+
+    /// Runs `f` on a freshly formatted filesystem.
+    fn with_fs(f: impl FnOnce(&dyn DynFilesystem)) {
+        let mut storage = formatted_storage();
+        Filesystem::mount_and_then(&mut storage, |fs| {
+            f(fs);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Appends to `/big` until fewer than [`LOW_SPACE_BLOCKS`] are free.
+    fn fill(fs: &dyn DynFilesystem) {
+        let flags = FileOpenFlags::WRITE | FileOpenFlags::CREATE | FileOpenFlags::APPEND;
+        while fs.available_blocks().unwrap() >= LOW_SPACE_BLOCKS {
+            fs.open_file_with_flags_and_then(flags, path!("/big"), &mut |file| {
+                file.write_all(&[0; 4096])
+            })
+            .unwrap();
+        }
+    }
+
+    fn write(fs: &dyn DynFilesystem, path: &Path, contents: &[u8]) {
+        fs.create_dir_all(path.parent().as_deref().unwrap())
+            .unwrap();
+        fs.write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn values_up_to_the_maximum_size_are_stored() {
+        with_fs(|fs| {
+            let value = [b'x'; VALUE_MAX_LEN - 1];
+            let data = encode(ValueType::Blob, &value).unwrap();
+            write_value(fs, DIR, KEY, &data).unwrap();
+            let read = read_value(fs, KEY, ValueType::Blob).unwrap();
+            assert_eq!(read.as_deref(), Some(&value[..]));
+        });
+        assert_eq!(
+            encode(ValueType::Blob, &[b'x'; VALUE_MAX_LEN]),
+            Err(KvError::TooLarge)
+        );
+    }
+
+    #[test]
+    fn values_are_read_back_only_as_their_type() {
+        with_fs(|fs| {
+            let data = encode(ValueType::I64, &postcard::to_allocvec(&-1i64).unwrap()).unwrap();
+            write_value(fs, DIR, KEY, &data).unwrap();
+            assert_eq!(
+                read_value(fs, KEY, ValueType::U64),
+                Err(KvError::TypeMismatch {
+                    expected: ValueType::U64,
+                    found: ValueType::I64,
+                })
+            );
+            write(fs, KEY, &[0, 1]);
+            assert_eq!(read_value(fs, KEY, ValueType::I64), Err(KvError::Corrupt));
+            write(fs, KEY, &[]);
+            assert_eq!(read_value(fs, KEY, ValueType::I64), Err(KvError::Corrupt));
+        });
+    }
+
+    #[test]
+    fn writing_an_unchanged_value_leaves_the_flash_alone() {
+        let data = encode(ValueType::Str, b"unchanged").unwrap();
+        let mut storage = formatted_storage();
+        Filesystem::mount_and_then(&mut storage, |fs| {
+            write_value(fs, DIR, KEY, &data).unwrap();
+            Ok(())
+        })
+        .unwrap();
+        let before = storage.into_inner().as_bytes().to_vec();
+
+        let flash = RamFlash::from_bytes(before.clone());
+        let mut storage = AppStorage::new(flash, 0, FILESYSTEM_SIZE).unwrap();
+        Filesystem::mount_and_then(&mut storage, |fs| {
+            write_value(fs, DIR, KEY, &data).unwrap();
+            Ok(())
+        })
+        .unwrap();
+        assert!(storage.into_inner().as_bytes() == before);
+    }
+
+    #[test]
+    fn missing_namespaces_are_empty() {
+        with_fs(|fs| {
+            assert_eq!(read_value(fs, KEY, ValueType::Str), Ok(None));
+            assert_eq!(remove_value(fs, KEY), Ok(false));
+            assert_eq!(list_keys(fs, DIR), Ok(Vec::new()));
+            assert_eq!(remove_namespace(fs, DIR), Ok(()));
+        });
+    }
+
+    #[test]
+    fn keys_skip_leftover_temporary_files() {
+        with_fs(|fs| {
+            write(fs, KEY, &[ValueType::Str as u8]);
+            write(fs, path!("/kv/test/other.tmp"), &[]);
+            assert_eq!(list_keys(fs, DIR), Ok(alloc::vec!["key".to_string()]));
+            assert_eq!(remove_value(fs, KEY), Ok(true));
+            remove_namespace(fs, DIR).unwrap();
+            assert_eq!(list_keys(fs, DIR), Ok(Vec::new()));
+        });
+    }
+
+    #[test]
+    fn compaction_removes_leftovers_and_empty_namespaces() {
+        with_fs(|fs| {
+            assert_eq!(compact_fs(fs), Ok(0));
+            write(fs, KEY, &[ValueType::Str as u8]);
+            write(fs, path!("/kv/test/key.tmp"), &[]);
+            write(fs, path!("/kv/gone/key.tmp"), &[]);
+            fs.create_dir_all(path!("/kv/empty")).unwrap();
+            // not a namespace, so not touched
+            fs.write(path!("/kv/stray"), b"stray").unwrap();
+
+            // the two leftovers and the two namespaces without keys
+            assert_eq!(compact_fs(fs), Ok(4));
+            assert_eq!(list_files(fs, KV_DIR).unwrap().len(), 2);
+            assert_eq!(list_files(fs, DIR), Ok(alloc::vec!["key".to_string()]));
+            assert!(fs.exists(path!("/kv/stray")));
+        });
+    }
+
+    #[test]
+    fn low_space_writes_compact_first() {
+        with_fs(|fs| {
+            for i in 0..5 {
+                write(
+                    fs,
+                    &to_path(&format!("{KV_DIR}/ns{i}/key.tmp")).unwrap(),
+                    &[],
+                );
+            }
+            fill(fs);
+            assert_eq!(ensure_space(fs), Ok(()));
+            assert_eq!(list_files(fs, KV_DIR), Ok(Vec::new()));
+        });
+    }
+
+    #[test]
+    fn low_space_writes_fail_if_compaction_does_not_help() {
+        with_fs(|fs| {
+            fill(fs);
+            let data = encode(ValueType::Str, b"value").unwrap();
+            assert_eq!(write_value(fs, DIR, KEY, &data), Err(KvError::NoSpace));
+            assert!(!fs.exists(DIR));
+        });
+    }
+}
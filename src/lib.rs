@@ -1,8 +1,24 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 extern crate alloc;
 
 //mod filesystem;
 pub mod config;
 pub mod filesystem;
+pub mod kv;
+#[cfg(target_arch = "xtensa")]
+pub mod logger;
+/// On the host, only the level filters.
+#[cfg(not(target_arch = "xtensa"))]
+pub mod logger {
+    pub mod filter;
+}
+#[cfg(target_arch = "xtensa")]
 pub mod net;
+/// On the host, only the certificates the default config refers to.
+#[cfg(not(target_arch = "xtensa"))]
+pub mod net {
+    pub mod ca_certs;
+}
+pub mod time;
+#[cfg(target_arch = "xtensa")]
 pub mod wifi;
@@ -0,0 +1,164 @@
+use core::str::FromStr;
+use heapless::{String, Vec};
+use log::LevelFilter;
+
+/// Maximum number of per-module filters.
+pub const MAX_FILTERS: usize = 16;
+/// Maximum length of a module path in a filter.
+pub const MODULE_MAX_LEN: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum FilterError {
+    #[error("Invalid log level in filter")]
+    InvalidLevel,
+    #[error("Module path is longer than {MODULE_MAX_LEN} bytes")]
+    ModuleTooLong,
+    #[error("More than {MAX_FILTERS} module filters")]
+    TooManyFilters,
+}
+
+/// The level of each module, set with [`Logger`](super::Logger) or parsed
+/// from the `ESP_LOG` syntax.
+#[derive(Debug, Clone)]
+pub struct Filters {
+    pub(super) default: LevelFilter,
+    /// Module path prefixes and their levels. The longest match wins.
+    pub(super) modules: Vec<(String<MODULE_MAX_LEN>, LevelFilter), MAX_FILTERS>,
+}
+
+impl Filters {
+    pub(super) const fn new() -> Self {
+        Self {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        }
+    }
+
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| is_in_module(target, module))
+            .max_by_key(|(module, _)| module.len())
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    pub fn set(&mut self, module: &str, level: LevelFilter) -> Result<(), FilterError> {
+        if let Some((_, current)) = self.modules.iter_mut().find(|(m, _)| m == module) {
+            *current = level;
+            return Ok(());
+        }
+        let module = String::try_from(module).map_err(|_| FilterError::ModuleTooLong)?;
+        self.modules
+            .push((module, level))
+            .map_err(|_| FilterError::TooManyFilters)
+    }
+
+    pub fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+/// Parses the `ESP_LOG` syntax: comma-separated `level` or `module=level`
+/// entries, e.g. `error,esp_test=info`. A bare level sets the default.
+impl FromStr for Filters {
+    type Err = FilterError;
+
+    fn from_str(spec: &str) -> Result<Self, FilterError> {
+        let mut filters = Self::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once('=') {
+                Some((module, level)) => filters.set(module.trim(), parse_level(level)?)?,
+                None => filters.default = parse_level(entry)?,
+            }
+        }
+        Ok(filters)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, FilterError> {
+    level.trim().parse().map_err(|_| FilterError::InvalidLevel)
+}
+
+/// Whether `target` is `module` or one of its submodules.
+fn is_in_module(target: &str, module: &str) -> bool {
+    target
+        .strip_prefix(module)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    fn filters(spec: &str) -> Filters {
+        spec.parse().unwrap()
+    }
+
+    #[test]
+    fn a_bare_level_sets_the_default() {
+        let debug = filters("warn,esp_test=debug");
+        assert_eq!(debug.level_for("esp_wifi"), LevelFilter::Warn);
+        assert_eq!(debug.level_for("esp_test"), LevelFilter::Debug);
+        assert_eq!(debug.level_for("esp_test::kv"), LevelFilter::Debug);
+        assert_eq!(debug.max_level(), LevelFilter::Debug);
+
+        let trace = filters(" esp_test = trace , , off ");
+        assert_eq!(trace.level_for("esp_test"), LevelFilter::Trace);
+        assert_eq!(trace.level_for("esp_hal"), LevelFilter::Off);
+        assert_eq!(filters("").level_for("esp_hal"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn the_longest_module_wins() {
+        let filters = filters("esp_test::net=trace,esp_test=error");
+        assert_eq!(filters.level_for("esp_test::net"), LevelFilter::Trace);
+        assert_eq!(filters.level_for("esp_test::net::dns"), LevelFilter::Trace);
+        // a longer name, not a submodule
+        assert_eq!(filters.level_for("esp_test::network"), LevelFilter::Error);
+        assert_eq!(filters.level_for("esp_test"), LevelFilter::Error);
+        assert_eq!(filters.level_for("esp_testing"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn a_repeated_module_keeps_the_last_level() {
+        let filters = filters("esp_test=debug,esp_test=warn");
+        assert_eq!(filters.modules.len(), 1);
+        assert_eq!(filters.level_for("esp_test"), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        for spec in ["loud", "esp_test=loud", "esp_test=", "=", "warn,esp_test"] {
+            assert_eq!(
+                spec.parse::<Filters>().err(),
+                Some(FilterError::InvalidLevel),
+                "{spec:?}"
+            );
+        }
+        let module = "m".repeat(MODULE_MAX_LEN + 1);
+        assert_eq!(
+            format!("{module}=info").parse::<Filters>().err(),
+            Some(FilterError::ModuleTooLong)
+        );
+        assert!(format!("{}=info", &module[1..]).parse::<Filters>().is_ok());
+    }
+
+    #[test]
+    fn at_most_max_filters_modules_are_kept() {
+        let spec = |count| {
+            (0..count)
+                .map(|i| format!("module{i}=debug"))
+                .collect::<alloc::vec::Vec<_>>()
+                .join(",")
+        };
+        assert_eq!(filters(&spec(MAX_FILTERS)).modules.len(), MAX_FILTERS);
+        assert_eq!(
+            spec(MAX_FILTERS + 1).parse::<Filters>().err(),
+            Some(FilterError::TooManyFilters)
+        );
+    }
+}
@@ -0,0 +1,164 @@
+pub mod filter;
+
+use crate::filesystem::logfile;
+use crate::time::CLOCK;
+use core::cell::RefCell;
+use core::fmt;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Instant;
+use filter::Filters;
+use log::{LevelFilter, Log, Metadata, Record};
+
+pub use filter::{FilterError, MAX_FILTERS, MODULE_MAX_LEN};
+
+/// The logger installed by [`init_logger_from_env`].
+pub static LOGGER: Logger = Logger::new();
+
+/// Prints log records with a timestamp: UTC once the clock is synchronised,
+/// uptime before that. Records are also written to flash once
+/// [`logfile::logfile_task`] runs.
+///
+/// Levels are filtered per module, and the filters can be changed at
+/// runtime.
+pub struct Logger {
+    filters: Mutex<CriticalSectionRawMutex, RefCell<Filters>>,
+}
+
+impl Logger {
+    const fn new() -> Self {
+        Self {
+            filters: Mutex::new(RefCell::new(Filters::new())),
+        }
+    }
+
+    /// Sets the level for modules without a filter of their own.
+    pub fn set_default_level(&self, level: LevelFilter) {
+        self.update(|filters| {
+            filters.default = level;
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Sets the level for `module` and its submodules, e.g. `esp_wifi` or
+    /// `esp_test::net`.
+    pub fn set_module_level(&self, module: &str, level: LevelFilter) -> Result<(), FilterError> {
+        self.update(|filters| filters.set(module, level))
+    }
+
+    /// Removes the filter for `module`, which then uses the level of its
+    /// closest parent filter or the default.
+    pub fn clear_module_level(&self, module: &str) {
+        self.update(|filters| {
+            filters.modules.retain(|(m, _)| m != module);
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    /// Replaces all filters by `spec`, in the same syntax as the `ESP_LOG`
+    /// environment variable, e.g. `warn,esp_test=debug`.
+    ///
+    /// On error the current filters are kept.
+    pub fn set_filters(&self, spec: &str) -> Result<(), FilterError> {
+        let new = spec.parse::<Filters>()?;
+        self.update(|filters| {
+            *filters = new;
+            Ok(())
+        })
+    }
+
+    /// The level in effect for records from `target`.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.filters
+            .lock(|filters| filters.borrow().level_for(target))
+    }
+
+    fn update(
+        &self,
+        f: impl FnOnce(&mut Filters) -> Result<(), FilterError>,
+    ) -> Result<(), FilterError> {
+        let max_level = self.filters.lock(|filters| {
+            let mut filters = filters.borrow_mut();
+            f(&mut filters).map(|()| filters.max_level())
+        })?;
+        // lets the `log` macros skip records no filter can pass
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let timestamp = Timestamp::now();
+        esp_println::println!(
+            "{} {:<5} {} - {}",
+            timestamp,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        if !logfile::enabled(record.level()) {
+            return;
+        }
+        // formats the arguments a second time, so only when they are kept
+        logfile::push(
+            record.level(),
+            format_args!(
+                "{} {:<5} {} - {}",
+                timestamp,
+                record.level(),
+                record.target(),
+                record.args()
+            ),
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+enum Timestamp {
+    Utc(crate::time::DateTime),
+    Uptime(Instant),
+}
+
+impl Timestamp {
+    fn now() -> Self {
+        match CLOCK.now_utc() {
+            Some(utc) => Timestamp::Utc(utc),
+            None => Timestamp::Uptime(Instant::now()),
+        }
+    }
+}
+
+/// UTC as ISO 8601, uptime as seconds with a `+` prefix, e.g. `+12.345678`.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timestamp::Utc(utc) => write!(f, "{utc}"),
+            Timestamp::Uptime(uptime) => {
+                let us = uptime.as_micros();
+                write!(f, "+{}.{:06}", us / 1_000_000, us % 1_000_000)
+            }
+        }
+    }
+}
+
+/// Installs [`LOGGER`] with the filters from the `ESP_LOG` environment
+/// variable at build time, or `info` if it is unset or invalid.
+pub fn init_logger_from_env() {
+    log::set_logger(&LOGGER).unwrap();
+    let spec = option_env!("ESP_LOG").unwrap_or("info");
+    if let Err(e) = LOGGER.set_filters(spec) {
+        LOGGER.set_default_level(LevelFilter::Info);
+        log::warn!("Ignoring ESP_LOG={spec}: {e}");
+    }
+}
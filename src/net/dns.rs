@@ -0,0 +1,171 @@
+use core::cell::RefCell;
+use core::net::{IpAddr, SocketAddr};
+use embassy_net::Stack;
+use embassy_net::dns::{DnsQueryType, Error};
+use embassy_net::tcp;
+use embassy_net::tcp::client::{TcpClient, TcpConnection};
+use embassy_time::{Duration, with_timeout};
+use embedded_nal_async::{AddrType, Dns, TcpConnect};
+use heapless::Vec;
+use log::debug;
+
+/// Maximum number of addresses returned by [`resolve`].
+pub const MAX_CANDIDATES: usize = 4;
+/// How long [`FallbackTcpClient`] waits for an address to answer before it
+/// tries the next one. The last one gets the full TCP timeout.
+const CONNECT_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The record types worth querying, preferred family first.
+///
+/// IPv6 is preferred whenever the stack has an IPv6 address, as recommended
+/// by RFC 8305 (Happy Eyeballs). A family without an address is not queried.
+fn query_types(stack: Stack<'_>) -> &'static [DnsQueryType] {
+    match (stack.config_v4().is_some(), stack.config_v6().is_some()) {
+        (true, true) => &[DnsQueryType::Aaaa, DnsQueryType::A],
+        (false, true) => &[DnsQueryType::Aaaa],
+        _ => &[DnsQueryType::A],
+    }
+}
+
+/// Resolves `host` for every configured address family.
+///
+/// The addresses alternate between families, preferred family first, so a
+/// caller that tries them in order falls back to the other family as soon as
+/// one address does not answer.
+pub async fn resolve(stack: Stack<'_>, host: &str) -> Result<Vec<IpAddr, MAX_CANDIDATES>, Error> {
+    let mut families: [Vec<IpAddr, MAX_CANDIDATES>; 2] = Default::default();
+    let mut last_error = Error::Failed;
+    for (qtype, addrs) in query_types(stack).iter().zip(families.iter_mut()) {
+        match stack.dns_query(host, *qtype).await {
+            Ok(found) => addrs.extend(found.into_iter().take(MAX_CANDIDATES).map(IpAddr::from)),
+            Err(e) => {
+                debug!("{qtype:?} lookup for {host} failed: {e:?}");
+                last_error = e;
+            }
+        }
+    }
+
+    let [preferred, fallback] = &families;
+    let candidates: Vec<IpAddr, MAX_CANDIDATES> = (0..MAX_CANDIDATES)
+        .flat_map(|i| [preferred.get(i), fallback.get(i)])
+        .flatten()
+        .copied()
+        .take(MAX_CANDIDATES)
+        .collect();
+    if candidates.is_empty() {
+        return Err(last_error);
+    }
+    Ok(candidates)
+}
+
+/// An `embedded-nal-async` resolver that falls back to the other address
+/// family when the preferred one has no usable records.
+///
+/// Unlike `DnsSocket`, a failed AAAA lookup does not fail the whole lookup.
+/// `embedded-nal-async` only passes one address on to the connect, so the
+/// other candidates of the last lookup are kept for [`FallbackTcpClient`].
+pub struct DualStackDns<'a> {
+    stack: Stack<'a>,
+    /// The addresses of the last lookup of either family, in [`resolve`]
+    /// order.
+    candidates: RefCell<Vec<IpAddr, MAX_CANDIDATES>>,
+}
+
+impl<'a> DualStackDns<'a> {
+    pub fn new(stack: Stack<'a>) -> Self {
+        Self {
+            stack,
+            candidates: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The addresses to try for `addr`: all candidates of the lookup that
+    /// returned it, or only `addr` if another lookup came in between.
+    fn candidates_for(&self, addr: IpAddr) -> Vec<IpAddr, MAX_CANDIDATES> {
+        let candidates = self.candidates.borrow();
+        match candidates.first() {
+            Some(&first) if first == addr => candidates.clone(),
+            _ => Vec::from_slice(&[addr]).unwrap(),
+        }
+    }
+}
+
+impl Dns for DualStackDns<'_> {
+    type Error = Error;
+
+    async fn get_host_by_name(&self, host: &str, addr_type: AddrType) -> Result<IpAddr, Error> {
+        let qtype = match addr_type {
+            AddrType::IPv4 => DnsQueryType::A,
+            AddrType::IPv6 => DnsQueryType::Aaaa,
+            AddrType::Either => {
+                let candidates = resolve(self.stack, host).await?;
+                let first = candidates[0];
+                *self.candidates.borrow_mut() = candidates;
+                return Ok(first);
+            }
+        };
+        let addrs = self.stack.dns_query(host, qtype).await?;
+        addrs.first().map(|&addr| addr.into()).ok_or(Error::Failed)
+    }
+
+    async fn get_host_by_address(&self, _addr: IpAddr, _result: &mut [u8]) -> Result<usize, Error> {
+        Err(Error::Failed)
+    }
+}
+
+/// A [`TcpClient`] that tries every address [`DualStackDns`] found for a
+/// host, in order, until one accepts the connection.
+///
+/// The addresses are tried one after the other rather than raced as in
+/// RFC 8305, as every attempt takes a connection of the pool. An address
+/// that does not answer within [`CONNECT_ATTEMPT_TIMEOUT`] is given up for
+/// the next one.
+pub struct FallbackTcpClient<'a, const N: usize, const TX_SZ: usize, const RX_SZ: usize> {
+    client: TcpClient<'a, N, TX_SZ, RX_SZ>,
+    dns: &'a DualStackDns<'a>,
+}
+
+impl<'a, const N: usize, const TX_SZ: usize, const RX_SZ: usize>
+    FallbackTcpClient<'a, N, TX_SZ, RX_SZ>
+{
+    pub fn new(client: TcpClient<'a, N, TX_SZ, RX_SZ>, dns: &'a DualStackDns<'a>) -> Self {
+        Self { client, dns }
+    }
+}
+
+impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize> TcpConnect
+    for FallbackTcpClient<'_, N, TX_SZ, RX_SZ>
+{
+    type Error = tcp::Error;
+    type Connection<'m>
+        = TcpConnection<'m, N, TX_SZ, RX_SZ>
+    where
+        Self: 'm;
+
+    async fn connect<'m>(&'m self, remote: SocketAddr) -> Result<Self::Connection<'m>, tcp::Error> {
+        let candidates = self.dns.candidates_for(remote.ip());
+        let mut last_error = tcp::Error::ConnectionReset;
+        for (i, &ip) in candidates.iter().enumerate() {
+            let addr = SocketAddr::new(ip, remote.port());
+            let result = if i + 1 == candidates.len() {
+                self.client.connect(addr).await
+            } else {
+                match with_timeout(CONNECT_ATTEMPT_TIMEOUT, self.client.connect(addr)).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        debug!("No answer from {addr}, trying the next address");
+                        continue;
+                    }
+                }
+            };
+            match result {
+                Ok(connection) => return Ok(connection),
+                Err(e) => {
+                    debug!("Connecting to {addr} failed: {e:?}");
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
@@ -0,0 +1,108 @@
+use super::NetClientFactory;
+use super::dns::FallbackTcpClient;
+use crate::config;
+use crate::time::{BUILD_TIME_US, parse_http_date};
+use embassy_time::{Duration, Instant, with_timeout};
+use log::{debug, info};
+use reqwless::request::Method;
+
+/// The `Date` header only has whole seconds, so on average the server time
+/// is half a second later than it says.
+const DATE_RESOLUTION_US: u64 = 1_000_000;
+/// Room for the status line and headers of a HEAD response.
+const HEADER_BUFFER_LEN: usize = 2048;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("HTTP request failed: {0:?}")]
+    RequestFailed(reqwless::Error),
+    #[error("Timeout getting HTTP time")]
+    Timeout,
+    #[error("Response has no Date header")]
+    MissingDate,
+    #[error("Response has an invalid Date header")]
+    InvalidDate,
+    #[error("Date header is older than the firmware")]
+    BeforeBuild,
+}
+
+/// The time from the `Date` header of an HTTP response.
+#[derive(Debug, Clone, Copy)]
+pub struct HttpTimeSample {
+    /// The `Date` header in microseconds since the Unix epoch.
+    pub server_time_us: u64,
+    /// Round trip of the HEAD request, without connecting.
+    pub roundtrip_us: u64,
+    received_at: Instant,
+}
+
+impl HttpTimeSample {
+    /// The current UTC time according to this sample, in microseconds since
+    /// the Unix epoch.
+    pub fn utc_now_us(&self) -> u64 {
+        self.server_time_us
+            + DATE_RESOLUTION_US / 2
+            + self.roundtrip_us / 2
+            + self.received_at.elapsed().as_micros()
+    }
+
+    /// How far off [`Self::utc_now_us`] can be.
+    pub fn uncertainty_us(&self) -> u64 {
+        (DATE_RESOLUTION_US + self.roundtrip_us) / 2
+    }
+}
+
+/// Sends a HEAD request to `http_time.url` and reads the time from the
+/// `Date` header of the response, whatever its status.
+///
+/// Before the first sync the RTC only holds the build time that
+/// [`Clock::init`](crate::time::Clock::init) put there, so that is what the
+/// HTTPS certificate is checked against. A `Date` older than the build is
+/// rejected, which stops a misconfigured server from turning the clock back.
+pub async fn get_time_using_http<'a, const N: usize, const TX_SZ: usize, const RX_SZ: usize>(
+    factory: &'a NetClientFactory<'a, N, TX_SZ, RX_SZ>,
+    tcp_client: &'a FallbackTcpClient<'a, N, TX_SZ, RX_SZ>,
+    url: &str,
+    http_time: &config::HttpTime,
+) -> Result<HttpTimeSample, Error> {
+    let mut client = if url.starts_with("https://") {
+        factory.new_https_client(tcp_client).await
+    } else {
+        factory.new_http_client(tcp_client)
+    };
+    let mut header_buf = [0; HEADER_BUFFER_LEN];
+    let timeout = Duration::from_millis(http_time.timeout_ms);
+    let (date, roundtrip_us, received_at) = with_timeout(timeout, async {
+        let mut request = client
+            .request(Method::HEAD, url)
+            .await
+            .map_err(Error::RequestFailed)?;
+        let sent_at = Instant::now();
+        let response = request
+            .send(&mut header_buf)
+            .await
+            .map_err(Error::RequestFailed)?;
+        let received_at = Instant::now();
+        let date = response
+            .headers()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Date"))
+            .map(|(_, value)| core::str::from_utf8(value).map_err(|_| Error::InvalidDate))
+            .ok_or(Error::MissingDate)??;
+        let date = parse_http_date(date).ok_or(Error::InvalidDate)?;
+        Ok::<_, Error>((date, (received_at - sent_at).as_micros(), received_at))
+    })
+    .await
+    .map_err(|_| Error::Timeout)??;
+
+    if date < BUILD_TIME_US {
+        return Err(Error::BeforeBuild);
+    }
+    let sample = HttpTimeSample {
+        server_time_us: date,
+        roundtrip_us,
+        received_at,
+    };
+    debug!("HTTP time sample: {sample:?}");
+    info!("Time from {url}: round trip {roundtrip_us} us");
+    Ok(sample)
+}
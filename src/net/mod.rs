@@ -1,21 +1,50 @@
 extern crate alloc;
 
 pub mod ca_certs;
+pub mod dns;
+pub mod http_time;
 pub mod ntp;
+pub mod slaac;
 
+use crate::config::{CONFIG, ConfigReceiver};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use dns::{DualStackDns, FallbackTcpClient};
 use embassy_net::Stack;
-use embassy_net::dns::DnsSocket;
 use embassy_net::tcp::client::{TcpClient, TcpClientState};
 use esp_hal::peripherals;
 use esp_mbedtls::{Certificates, Tls};
 use reqwless::X509;
 use reqwless::client::{HttpClient, TlsConfig};
+use static_cell::StaticCell;
+
+/// TCP connections shared by the application and the time sync task.
+const SHARED_CONNECTIONS: usize = 2;
+
+/// The factory shared by the application and the time sync task.
+pub type SharedNetClientFactory = NetClientFactory<'static, SHARED_CONNECTIONS, 1024, 1024>;
+pub type SharedTcpClient = FallbackTcpClient<'static, SHARED_CONNECTIONS, 1024, 1024>;
+
+static CLIENT_FACTORY: StaticCell<SharedNetClientFactory> = StaticCell::new();
+
+/// Creates the [`SharedNetClientFactory`]. Call once, as it takes the
+/// hardware crypto peripherals.
+pub fn init_client_factory(
+    stack: Stack<'static>,
+    sha: peripherals::SHA<'static>,
+    rsa: peripherals::RSA<'static>,
+) -> &'static SharedNetClientFactory {
+    CLIENT_FACTORY.init(NetClientFactory::new(stack, sha, rsa))
+}
 
 pub struct NetClientFactory<'a, const N: usize, const TX_SZ: usize, const RX_SZ: usize> {
     stack: Stack<'a>,
     state: TcpClientState<N, TX_SZ, RX_SZ>,
-    dns: DnsSocket<'a>,
+    dns: DualStackDns<'a>,
     tls: Tls<'a>,
+    /// Every CA certificate used since boot, the current one last.
+    ca_certs: RefCell<Vec<&'static [u8]>>,
+    config_changes: RefCell<Option<ConfigReceiver<'static>>>,
 }
 
 impl<'a, const N: usize, const TX_SZ: usize, const RX_SZ: usize>
@@ -25,23 +54,62 @@ impl<'a, const N: usize, const TX_SZ: usize, const RX_SZ: usize>
         Self {
             stack,
             state: TcpClientState::new(),
-            dns: DnsSocket::new(stack),
+            dns: DualStackDns::new(stack),
             tls: Tls::new(sha).unwrap().with_hardware_rsa(rsa),
+            ca_certs: RefCell::new(Vec::new()),
+            config_changes: RefCell::new(CONFIG.subscribe()),
         }
     }
 
-    pub fn new_tcp_client(&'a self) -> TcpClient<'a, N, TX_SZ, RX_SZ> {
-        TcpClient::new(self.stack, &self.state)
+    /// Picks up the CA certificate from the config store on first use and
+    /// whenever the config has changed since.
+    ///
+    /// Certificates are leaked, as HTTPS clients borrow them for `'a`, but
+    /// each distinct one only once: switching back to an earlier CA reuses
+    /// its copy, so the leak is bounded by the CAs configured since boot
+    /// rather than by the number of changes.
+    async fn refresh_ca_cert(&self) -> &'static [u8] {
+        let changed = self
+            .config_changes
+            .borrow_mut()
+            .as_mut()
+            .is_some_and(|changes| changes.try_changed().is_some());
+        let current = self.ca_certs.borrow().last().copied();
+        if let (false, Some(current)) = (changed, current) {
+            return current;
+        }
+        let config = CONFIG.lock().await;
+        let pem = config.net.https.ca_cert.pem.as_slice();
+        let mut ca_certs = self.ca_certs.borrow_mut();
+        let cert: &'static [u8] = match ca_certs.iter().position(|&cert| cert == pem) {
+            Some(i) => ca_certs.remove(i),
+            None => Vec::leak(pem.to_vec()),
+        };
+        ca_certs.push(cert);
+        cert
+    }
+
+    /// A TCP client that tries every address of a host, see
+    /// [`FallbackTcpClient`].
+    pub fn new_tcp_client(&'a self) -> FallbackTcpClient<'a, N, TX_SZ, RX_SZ> {
+        FallbackTcpClient::new(TcpClient::new(self.stack, &self.state), &self.dns)
+    }
+
+    pub fn new_http_client(
+        &'a self,
+        tcp_client: &'a FallbackTcpClient<'a, N, TX_SZ, RX_SZ>,
+    ) -> HttpClient<'a, FallbackTcpClient<'a, N, TX_SZ, RX_SZ>, DualStackDns<'a>> {
+        HttpClient::new(tcp_client, &self.dns)
     }
 
-    pub fn new_https_client(
+    pub async fn new_https_client(
         &'a self,
-        tcp_client: &'a TcpClient<'a, N, TX_SZ, RX_SZ>,
-    ) -> HttpClient<'a, TcpClient<'a, N, TX_SZ, RX_SZ>, DnsSocket<'a>> {
+        tcp_client: &'a FallbackTcpClient<'a, N, TX_SZ, RX_SZ>,
+    ) -> HttpClient<'a, FallbackTcpClient<'a, N, TX_SZ, RX_SZ>, DualStackDns<'a>> {
+        let ca_cert = self.refresh_ca_cert().await;
         let mut certificates = Certificates::new();
-        let cert = &crate::config::CONFIG.net.https.ca_cert.pem;
         certificates.ca_chain =
-            Some(X509::pem(cert).expect("Bug in CA certificate of lets encrypt. Failed to parse."));
+            Some(X509::pem(ca_cert).expect("Bug in CA certificate validation. Failed to parse."));
 
         HttpClient::new_with_tls(
             tcp_client,
@@ -1,22 +1,129 @@
-use core::net::{IpAddr, SocketAddr};
+use super::dns;
+use crate::config;
+use core::cell::Cell;
+use core::net::SocketAddr;
 use embassy_net::Stack;
-use embassy_net::dns::DnsQueryType;
-use embassy_net::udp::{PacketMetadata, UdpSocket};
-use embassy_time::Instant;
+use embassy_net::udp::{BindError, PacketMetadata, UdpSocket};
+use embassy_time::{Duration, Instant, with_timeout};
 
-use log::{error, info};
-use sntpc::{NtpContext, NtpResult, NtpTimestampGenerator, get_time};
+use log::{debug, error, info, warn};
+use sntpc::{NtpContext, NtpResult, NtpTimestampGenerator, NtpUdpSocket, get_time};
 
-const NTP_SERVER: &str = "pool.ntp.org";
+/// Stratum 16 means the server itself is not synchronised (RFC 5905).
+const STRATUM_UNSYNCHRONISED: u8 = 16;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error("Failed to Setup UDP for NTP")]
-    SetupUdpFailed,
-    #[error("Failed to resolve DNS")]
-    DnsResolutionFailed,
-    #[error("Failed to get NTP time")]
-    NtpTimeFailed,
+    #[error("Failed to Setup UDP for NTP: {0:?}")]
+    SetupUdpFailed(BindError),
+    #[error("Failed to resolve DNS: {0:?}")]
+    DnsResolutionFailed(embassy_net::dns::Error),
+    #[error("Failed to get NTP time: {0:?}")]
+    NtpTimeFailed(sntpc::Error),
+    #[error("Timeout getting NTP time")]
+    Timeout,
+    #[error("NTP sample rejected: {0}")]
+    Rejected(Rejection),
+}
+
+/// Why an otherwise valid NTP response is not used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum Rejection {
+    #[error("server is not synchronised")]
+    Unsynchronised,
+    #[error("round-trip delay of {0} us is too high")]
+    HighRoundtrip(u64),
+}
+
+/// The leap indicator of an NTP response (RFC 5905, section 7.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeapIndicator {
+    NoWarning,
+    /// The last minute of the day has 61 seconds.
+    InsertSecond,
+    /// The last minute of the day has 59 seconds.
+    DeleteSecond,
+    /// The server clock is not synchronised.
+    Alarm,
+}
+
+impl LeapIndicator {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0 => Self::NoWarning,
+            1 => Self::InsertSecond,
+            2 => Self::DeleteSecond,
+            _ => Self::Alarm,
+        }
+    }
+}
+
+/// A single NTP response.
+#[derive(Debug, Clone, Copy)]
+pub struct NtpSample {
+    pub server: SocketAddr,
+    /// Server transmit time in microseconds since the Unix epoch.
+    pub server_time_us: u64,
+    pub roundtrip_us: u64,
+    /// Server time minus local uptime, corrected for half the round trip.
+    pub offset_us: i64,
+    pub stratum: u8,
+    pub leap: LeapIndicator,
+    /// Server clock precision as a power of two, in seconds.
+    pub precision: i8,
+}
+
+impl NtpSample {
+    fn new(server: SocketAddr, result: NtpResult, leap: LeapIndicator) -> Self {
+        Self {
+            server,
+            server_time_us: get_microseconds_from_ntp(result),
+            roundtrip_us: result.roundtrip,
+            offset_us: result.offset,
+            stratum: result.stratum,
+            leap,
+            precision: result.precision,
+        }
+    }
+
+    /// The current UTC time according to this sample, in microseconds since
+    /// the Unix epoch.
+    pub fn utc_now_us(&self) -> u64 {
+        Instant::now()
+            .as_micros()
+            .saturating_add_signed(self.offset_us)
+    }
+
+    fn check(&self, max_roundtrip_us: u64) -> Result<(), Rejection> {
+        if self.stratum >= STRATUM_UNSYNCHRONISED || self.leap == LeapIndicator::Alarm {
+            return Err(Rejection::Unsynchronised);
+        }
+        if self.roundtrip_us > max_roundtrip_us {
+            return Err(Rejection::HighRoundtrip(self.roundtrip_us));
+        }
+        Ok(())
+    }
+}
+
+/// Passes NTP packets through to the UDP socket and keeps the leap indicator
+/// of the last response, which `NtpResult` does not expose.
+struct LeapSniffer<'a, 'b> {
+    socket: &'a UdpSocket<'b>,
+    leap: Cell<LeapIndicator>,
+}
+
+impl NtpUdpSocket for LeapSniffer<'_, '_> {
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> sntpc::Result<usize> {
+        NtpUdpSocket::send_to(self.socket, buf, addr).await
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> sntpc::Result<(usize, SocketAddr)> {
+        let (len, addr) = NtpUdpSocket::recv_from(self.socket, buf).await?;
+        if let Some(first) = buf[..len].first() {
+            self.leap.set(LeapIndicator::from_bits(first >> 6));
+        }
+        Ok((len, addr))
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -58,7 +165,17 @@ pub fn get_microseconds_from_ntp(ntp_result: NtpResult) -> u64 {
     whole_seconds_micros + fraction_micros
 }
 
-pub async fn get_real_time_using_ntp(stack: Stack<'_>) -> Result<u64, Error> {
+/// Queries the configured servers in order and returns the best sample of
+/// the first address that yields a usable one.
+///
+/// Each address is queried `ntp.samples` times, each time for at most
+/// `ntp.timeout_ms`. Samples from unsynchronised servers or with a round trip
+/// above `ntp.max_roundtrip_ms` are rejected, and the one with the lowest
+/// round trip wins. On failure the last error is returned.
+pub async fn get_real_time_using_ntp(
+    stack: Stack<'_>,
+    ntp: &config::Ntp,
+) -> Result<NtpSample, Error> {
     info!("Waiting for network connection to be up.");
     // Wait for the tap interface to be up before continuing
     stack.wait_config_up().await;
@@ -77,33 +194,63 @@ pub async fn get_real_time_using_ntp(stack: Stack<'_>) -> Result<u64, Error> {
         &mut tx_meta,
         &mut tx_buffer,
     );
-    socket.bind(123).map_err(|_| Error::SetupUdpFailed)?;
+    socket.bind(123).map_err(Error::SetupUdpFailed)?;
+    let socket = LeapSniffer {
+        socket: &socket,
+        leap: Cell::new(LeapIndicator::NoWarning),
+    };
     let context = NtpContext::new(Timestamp::new());
 
-    let ntp_addrs = stack
-        .dns_query(NTP_SERVER, DnsQueryType::A)
-        .await
-        .map_err(|e| {
-            error!("Failed to resolve DNS: {e:?}");
-            Error::DnsResolutionFailed
-        })?;
-
-    if ntp_addrs.is_empty() {
-        error!("Failed to resolve DNS");
-        return Err(Error::DnsResolutionFailed);
-    }
-
-    let addr: IpAddr = ntp_addrs[0].into();
-    let result = get_time(SocketAddr::from((addr, 123)), &socket, context).await;
+    let timeout = Duration::from_millis(ntp.timeout_ms);
+    let max_roundtrip_us = ntp.max_roundtrip_ms * 1000;
+    let mut last_error = None;
+    for server in &ntp.servers {
+        let addrs = match dns::resolve(stack, server).await {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                error!("Failed to resolve {server}: {e:?}");
+                last_error = Some(Error::DnsResolutionFailed(e));
+                continue;
+            }
+        };
 
-    match result {
-        Ok(time) => {
-            info!("Time: {time:?}");
-            Ok(get_microseconds_from_ntp(time))
-        }
-        Err(e) => {
-            error!("Error getting time: {e:?}");
-            Err(Error::NtpTimeFailed)
+        // try the addresses in order, so an unreachable address family falls
+        // back to the other one
+        for addr in addrs {
+            let addr = SocketAddr::from((addr, ntp.port));
+            let mut best: Option<NtpSample> = None;
+            for _ in 0..ntp.samples {
+                let sample = match with_timeout(timeout, get_time(addr, &socket, context)).await {
+                    Ok(Ok(result)) => NtpSample::new(addr, result, socket.leap.get()),
+                    Ok(Err(e)) => {
+                        error!("Error getting time from {server} ({addr}): {e:?}");
+                        last_error = Some(Error::NtpTimeFailed(e));
+                        continue;
+                    }
+                    Err(_) => {
+                        warn!("Timeout getting time from {server} ({addr})");
+                        last_error = Some(Error::Timeout);
+                        continue;
+                    }
+                };
+                debug!("NTP sample: {sample:?}");
+                if let Err(rejection) = sample.check(max_roundtrip_us) {
+                    warn!("Rejected time from {server} ({addr}): {rejection}");
+                    last_error = Some(Error::Rejected(rejection));
+                    continue;
+                }
+                if best.is_none_or(|best| sample.roundtrip_us < best.roundtrip_us) {
+                    best = Some(sample);
+                }
+            }
+            if let Some(best) = best {
+                info!(
+                    "Time from {server} ({addr}): stratum {}, round trip {} us",
+                    best.stratum, best.roundtrip_us
+                );
+                return Ok(best);
+            }
         }
     }
+    Err(last_error.unwrap_or(Error::Timeout))
 }
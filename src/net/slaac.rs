@@ -0,0 +1,179 @@
+use core::net::Ipv6Addr;
+use embassy_net::raw::{IpProtocol, IpVersion, PacketMetadata, RawSocket};
+use embassy_net::{ConfigV6, HardwareAddress, Ipv6Cidr, Stack, StaticConfigV6};
+use embassy_time::{Duration, Instant, with_timeout};
+use esp_wifi::wifi::WifiDevice;
+use log::{info, warn};
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::wire::{
+    IPV6_HEADER_LEN, IPV6_LINK_LOCAL_ALL_ROUTERS, Icmpv6Packet, Icmpv6Repr, Ipv6Packet, Ipv6Repr,
+    NdiscPrefixInfoFlags, NdiscRepr,
+};
+
+/// Interval between router solicitations while unconfigured.
+const SOLICIT_INTERVAL: Duration = Duration::from_secs(4);
+/// Router solicitations sent before waiting for unsolicited advertisements
+/// only (RFC 4861, `MAX_RTR_SOLICITATIONS`).
+const MAX_SOLICITATIONS: u32 = 3;
+/// SLAAC only works with 64-bit prefixes on Ethernet-like links.
+const SLAAC_PREFIX_LEN: u8 = 64;
+/// Neighbor discovery messages must arrive with the maximum hop limit.
+const NDISC_HOP_LIMIT: u8 = 255;
+/// Size of a router solicitation without options.
+const ROUTER_SOLICIT_LEN: usize = 8;
+
+/// The parts of a router advertisement needed to configure an address.
+struct RouterAdvert {
+    /// The advertising router, unless it is not a default router.
+    gateway: Option<Ipv6Addr>,
+    prefix: Ipv6Addr,
+    valid_lifetime: Duration,
+}
+
+/// Configures a global IPv6 address from router advertisements.
+///
+/// smoltcp has no SLAAC, so this solicits a router, derives a modified
+/// EUI-64 address from the advertised prefix and keeps it until the prefix
+/// expires. DNS servers still come from DHCPv4 or the static IPv4 config.
+#[embassy_executor::task]
+pub async fn slaac_task(stack: Stack<'static>) {
+    let HardwareAddress::Ethernet(mac) = stack.hardware_address() else {
+        warn!("SLAAC needs an Ethernet hardware address");
+        return;
+    };
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1280];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; IPV6_HEADER_LEN + ROUTER_SOLICIT_LEN];
+    let socket = RawSocket::new::<WifiDevice<'static>>(
+        stack,
+        IpVersion::Ipv6,
+        IpProtocol::Icmpv6,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    stack.wait_link_up().await;
+    let mut solicitations = 0;
+    let mut expires_at: Option<Instant> = None;
+    let mut packet = [0; 1280];
+    loop {
+        if expires_at.is_none() && solicitations < MAX_SOLICITATIONS {
+            socket.send(&router_solicit()).await;
+            solicitations += 1;
+        }
+
+        let len = match with_timeout(SOLICIT_INTERVAL, socket.recv(&mut packet)).await {
+            Ok(Ok(len)) => len,
+            Ok(Err(e)) => {
+                warn!("Failed to receive ICMPv6 packet: {e:?}");
+                continue;
+            }
+            Err(_) => {
+                if expires_at.is_some_and(|at| at <= Instant::now()) {
+                    info!("IPv6 prefix expired");
+                    stack.set_config_v6(ConfigV6::None);
+                    expires_at = None;
+                    solicitations = 0;
+                }
+                continue;
+            }
+        };
+        let Some(advert) = parse_router_advert(&packet[..len]) else {
+            continue;
+        };
+
+        let address = Ipv6Cidr::new(eui64_address(advert.prefix, mac.0), SLAAC_PREFIX_LEN);
+        let gateway = advert.gateway;
+        let changed = stack
+            .config_v6()
+            .is_none_or(|c| c.address != address || c.gateway != gateway);
+        if changed {
+            info!("SLAAC address {address}, gateway {gateway:?}");
+            stack.set_config_v6(ConfigV6::Static(StaticConfigV6 {
+                address,
+                gateway,
+                dns_servers: Default::default(),
+            }));
+        }
+        expires_at = Some(Instant::now() + advert.valid_lifetime);
+    }
+}
+
+/// Builds a router solicitation from the unspecified address, which needs no
+/// link-local address to be configured first.
+fn router_solicit() -> [u8; IPV6_HEADER_LEN + ROUTER_SOLICIT_LEN] {
+    let icmp_repr = Icmpv6Repr::Ndisc(NdiscRepr::RouterSolicit { lladdr: None });
+    let ip_repr = Ipv6Repr {
+        src_addr: Ipv6Addr::UNSPECIFIED,
+        dst_addr: IPV6_LINK_LOCAL_ALL_ROUTERS,
+        next_header: IpProtocol::Icmpv6,
+        payload_len: icmp_repr.buffer_len(),
+        hop_limit: NDISC_HOP_LIMIT,
+    };
+    let mut buffer = [0; IPV6_HEADER_LEN + ROUTER_SOLICIT_LEN];
+    let mut ip_packet = Ipv6Packet::new_unchecked(&mut buffer[..]);
+    ip_repr.emit(&mut ip_packet);
+    icmp_repr.emit(
+        &ip_repr.src_addr,
+        &ip_repr.dst_addr,
+        &mut Icmpv6Packet::new_unchecked(ip_packet.payload_mut()),
+        &ChecksumCapabilities::default(),
+    );
+    buffer
+}
+
+/// Extracts an autoconfigurable prefix from a router advertisement. Any other
+/// packet yields `None`.
+fn parse_router_advert(packet: &[u8]) -> Option<RouterAdvert> {
+    let ip_packet = Ipv6Packet::new_checked(packet).ok()?;
+    let ip_repr = Ipv6Repr::parse(&ip_packet).ok()?;
+    if ip_repr.hop_limit != NDISC_HOP_LIMIT {
+        return None;
+    }
+    let icmp_packet = Icmpv6Packet::new_checked(ip_packet.payload()).ok()?;
+    let icmp_repr = Icmpv6Repr::parse(
+        &ip_repr.src_addr,
+        &ip_repr.dst_addr,
+        &icmp_packet,
+        &ChecksumCapabilities::default(),
+    )
+    .ok()?;
+    let Icmpv6Repr::Ndisc(NdiscRepr::RouterAdvert {
+        router_lifetime,
+        prefix_info: Some(prefix),
+        ..
+    }) = icmp_repr
+    else {
+        return None;
+    };
+    if !prefix.flags.contains(NdiscPrefixInfoFlags::ADDRCONF)
+        || prefix.prefix_len != SLAAC_PREFIX_LEN
+    {
+        return None;
+    }
+    Some(RouterAdvert {
+        gateway: (router_lifetime != smoltcp::time::Duration::ZERO).then_some(ip_repr.src_addr),
+        prefix: prefix.prefix,
+        valid_lifetime: Duration::from_millis(prefix.valid_lifetime.total_millis()),
+    })
+}
+
+/// Combines a /64 prefix with the modified EUI-64 interface identifier of
+/// `mac` (RFC 4291, appendix A).
+fn eui64_address(prefix: Ipv6Addr, mac: [u8; 6]) -> Ipv6Addr {
+    let mut octets = prefix.octets();
+    octets[8..].copy_from_slice(&[
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ]);
+    Ipv6Addr::from(octets)
+}
@@ -0,0 +1,115 @@
+use super::datetime::MICROS_PER_SECOND;
+use super::sync::{self, SyncStatus};
+use super::tz::LocalDateTime;
+use super::{DateTime, TimeZone};
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use esp_hal::rtc_cntl::Rtc;
+
+/// The wall clock, shared by everything that needs UTC time.
+pub static CLOCK: Clock = Clock::new();
+
+/// When the firmware was built, in microseconds since the Unix epoch. The
+/// real time is never earlier than this.
+pub const BUILD_TIME_US: u64 = parse_decimal(env!("BUILD_UNIX_TIME")) * MICROS_PER_SECOND;
+
+/// UTC time backed by the RTC, which [`sync::time_sync_task`] keeps in step
+/// with NTP.
+pub struct Clock {
+    rtc: Mutex<CriticalSectionRawMutex, RefCell<Option<Rtc<'static>>>>,
+}
+
+impl Clock {
+    const fn new() -> Self {
+        Self {
+            rtc: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Hands the RTC over to the clock. Call once at boot, before spawning
+    /// [`sync::time_sync_task`].
+    ///
+    /// An RTC behind [`BUILD_TIME_US`] is moved forward to it, so that TLS
+    /// certificates are checked against a plausible time before the first
+    /// sync.
+    pub fn init(&self, rtc: Rtc<'static>) {
+        if rtc.current_time_us() < BUILD_TIME_US {
+            rtc.set_current_time_us(BUILD_TIME_US);
+        }
+        self.rtc.lock(|cell| cell.replace(Some(rtc)));
+    }
+
+    /// Whether the RTC has been set from NTP. It stays set when a later
+    /// resync fails.
+    pub fn is_synchronised(&self) -> bool {
+        is_synchronised(sync::sync_state().status)
+    }
+
+    /// Waits until the RTC has been set from NTP.
+    pub async fn wait_synchronised(&self) {
+        sync::wait_for(|state| is_synchronised(state.status)).await;
+    }
+
+    /// The current UTC time in microseconds since the Unix epoch, or `None`
+    /// until the RTC has been set from NTP.
+    pub fn now_utc_us(&self) -> Option<u64> {
+        if !self.is_synchronised() {
+            return None;
+        }
+        self.rtc
+            .lock(|cell| cell.borrow().as_ref().map(Rtc::current_time_us))
+    }
+
+    /// The current UTC date and time, or `None` until the RTC has been set
+    /// from NTP.
+    pub fn now_utc(&self) -> Option<DateTime> {
+        self.now_utc_us().map(DateTime::from_unix_micros)
+    }
+
+    /// The current local date and time in `tz`, or `None` until the RTC has
+    /// been set from NTP.
+    pub fn now_local<'a>(&self, tz: &'a TimeZone) -> Option<LocalDateTime<'a>> {
+        self.now_utc_us().map(|us| tz.to_local(us))
+    }
+
+    /// Reads the RTC whether or not it has been set.
+    pub(crate) fn rtc_us(&self) -> u64 {
+        self.with_rtc(|rtc| rtc.current_time_us())
+    }
+
+    pub(crate) fn set_rtc_us(&self, us: u64) {
+        self.with_rtc(|rtc| rtc.set_current_time_us(us));
+    }
+
+    /// Moves the RTC by `delta_us` without losing the time spent in between.
+    pub(crate) fn adjust_rtc_us(&self, delta_us: i64) {
+        self.with_rtc(|rtc| {
+            rtc.set_current_time_us(rtc.current_time_us().saturating_add_signed(delta_us))
+        });
+    }
+
+    fn with_rtc<R>(&self, f: impl FnOnce(&Rtc<'static>) -> R) -> R {
+        self.rtc.lock(|cell| {
+            f(cell
+                .borrow()
+                .as_ref()
+                .expect("Clock::init must be called before syncing time"))
+        })
+    }
+}
+
+fn is_synchronised(status: SyncStatus) -> bool {
+    matches!(status, SyncStatus::Synchronised | SyncStatus::Stale)
+}
+
+const fn parse_decimal(digits: &str) -> u64 {
+    let digits = digits.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        value = value * 10 + (digits[i] - b'0') as u64;
+        i += 1;
+    }
+    value
+}
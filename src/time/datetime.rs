@@ -0,0 +1,254 @@
+use core::fmt;
+
+pub(crate) const MICROS_PER_SECOND: u64 = 1_000_000;
+pub(crate) const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const UNIX_EPOCH_YEAR: u16 = 1970;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A calendar date and time, in UTC unless it comes from a
+/// [`TimeZone`](super::TimeZone).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub micros: u32,
+}
+
+impl DateTime {
+    pub fn from_unix_micros(us: u64) -> Self {
+        let secs = us / MICROS_PER_SECOND;
+        let (year, month, day) = civil_from_days((secs / SECONDS_PER_DAY) as i64);
+        let secs_of_day = secs % SECONDS_PER_DAY;
+        Self {
+            year: year as u16,
+            month,
+            day,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+            micros: (us % MICROS_PER_SECOND) as u32,
+        }
+    }
+
+    pub fn to_unix_micros(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month, self.day) as u64;
+        let secs = days * SECONDS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64;
+        secs * MICROS_PER_SECOND + self.micros as u64
+    }
+}
+
+/// Formats as ISO 8601 in UTC, e.g. `2025-03-30T01:59:59.000000Z`.
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.micros
+        )
+    }
+}
+
+/// Parses an HTTP date into microseconds since the Unix epoch.
+///
+/// Accepts the three formats RFC 7231, section 7.1.1.1 requires recipients
+/// to handle:
+///
+/// - `Sun, 06 Nov 1994 08:49:37 GMT` (IMF-fixdate)
+/// - `Sunday, 06-Nov-94 08:49:37 GMT` (RFC 850)
+/// - `Sun Nov  6 08:49:37 1994` (asctime)
+///
+/// The day name is not checked.
+pub fn parse_http_date(date: &str) -> Option<u64> {
+    let (_day_name, rest) = date.trim().split_once([',', ' '])?;
+    let mut parts = [""; 5];
+    let mut len = 0;
+    for part in rest.split_ascii_whitespace() {
+        *parts.get_mut(len)? = part;
+        len += 1;
+    }
+
+    let (year, month, day, time) = match parts[..len] {
+        [day, month, year, time, "GMT"] => (parse_number(year, 4)?, month, day, time),
+        [date, time, "GMT"] => {
+            let mut fields = date.split('-');
+            let (day, month, year) = (fields.next()?, fields.next()?, fields.next()?);
+            if fields.next().is_some() {
+                return None;
+            }
+            // RFC 850 years have two digits. Read them as the closest year
+            // that is not too far in the future.
+            let year = match parse_number(year, 2)? {
+                year @ 0..70 => 2000 + year,
+                year => 1900 + year,
+            };
+            (year, month, day, time)
+        }
+        [month, day, time, year] => (parse_number(year, 4)?, month, day, time),
+        _ => return None,
+    };
+    if year < UNIX_EPOCH_YEAR {
+        return None;
+    }
+    let month = MONTHS.iter().position(|&m| m == month)? as u8 + 1;
+    let day = parse_number(day, 2)? as u8;
+    let mut fields = time.split(':');
+    let mut next_field = |max| {
+        fields
+            .next()
+            .and_then(|field| parse_number(field, 2))
+            .filter(|&value| value <= max)
+            .map(|value| value as u8)
+    };
+    let (hour, minute, second) = (next_field(23)?, next_field(59)?, next_field(60)?);
+    if fields.next().is_some() {
+        return None;
+    }
+
+    let datetime = DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        // a leap second is repeated rather than rolled over
+        second: second.min(59),
+        micros: 0,
+    };
+    let us = datetime.to_unix_micros();
+    // rejects days past the end of the month
+    (DateTime::from_unix_micros(us) == datetime).then_some(us)
+}
+
+/// A decimal number of 1 to `max_digits` digits.
+fn parse_number(digits: &str, max_digits: usize) -> Option<u16> {
+    if digits.is_empty() || digits.len() > max_digits || !digits.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Converts days since 1970-01-01 into a (year, month, day) date in the
+/// proleptic Gregorian calendar. See
+/// <https://howardhinnant.github.io/date_algorithms.html>.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // months counted from March, so that the leap day is last
+    let march_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * march_month + 2) / 5 + 1) as u8;
+    let month = if march_month < 10 {
+        march_month + 3
+    } else {
+        march_month - 9
+    } as u8;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// The inverse of [`civil_from_days`].
+pub(crate) fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = year - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let march_month = (month as i64 + 9) % 12;
+    let day_of_year = (153 * march_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `Sun, 06 Nov 1994 08:49:37 GMT`, the example in RFC 7231.
+    const EXAMPLE_US: u64 = 784_111_777 * MICROS_PER_SECOND;
+
+    fn datetime(date: &str) -> Option<String> {
+        parse_http_date(date).map(|us| DateTime::from_unix_micros(us).to_string())
+    }
+
+    #[test]
+    fn http_dates_are_parsed_in_all_three_formats() {
+        for date in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+            "Sun Nov 06 08:49:37 1994",
+            " Sun, 06 Nov 1994 08:49:37 GMT\r\n",
+        ] {
+            assert_eq!(parse_http_date(date), Some(EXAMPLE_US), "{date:?}");
+        }
+    }
+
+    #[test]
+    fn rfc_850_years_are_read_as_1970_to_2069() {
+        assert_eq!(parse_http_date("Thursday, 01-Jan-70 00:00:00 GMT"), Some(0));
+        assert_eq!(
+            datetime("Friday, 31-Dec-99 23:59:59 GMT").unwrap(),
+            "1999-12-31T23:59:59.000000Z"
+        );
+        assert_eq!(
+            datetime("Saturday, 01-Jan-00 00:00:00 GMT").unwrap(),
+            "2000-01-01T00:00:00.000000Z"
+        );
+        assert_eq!(
+            datetime("Tuesday, 31-Dec-69 23:59:59 GMT").unwrap(),
+            "2069-12-31T23:59:59.000000Z"
+        );
+        assert_eq!(parse_http_date("Sunday, 06-Nov-1994 08:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn leap_seconds_repeat_the_last_second() {
+        assert_eq!(
+            datetime("Sat, 31 Dec 2016 23:59:60 GMT").unwrap(),
+            "2016-12-31T23:59:59.000000Z"
+        );
+        assert_eq!(parse_http_date("Sat, 31 Dec 2016 23:59:61 GMT"), None);
+    }
+
+    #[test]
+    fn invalid_http_dates_are_rejected() {
+        for date in [
+            "",
+            "Sun",
+            // no such day
+            "Sat, 31 Feb 2024 00:00:00 GMT",
+            "Wed, 29 Feb 2023 00:00:00 GMT",
+            "Sun, 00 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 06 nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:60:37 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, 06 Nov 1994 08:49:37:00 GMT",
+            "Sun, 06 Nov 1994 08:49:+7 GMT",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Nov 1994 08:49:37 GMT extra",
+            "Sun, 06 Nov 94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 94",
+            "Sunday, 06-Nov-94-1 08:49:37 GMT",
+            // before the Unix epoch
+            "Wed, 31 Dec 1969 23:59:59 GMT",
+        ] {
+            assert_eq!(parse_http_date(date), None, "{date:?}");
+        }
+    }
+}
@@ -0,0 +1,11 @@
+#[cfg(target_arch = "xtensa")]
+mod clock;
+mod datetime;
+#[cfg(target_arch = "xtensa")]
+pub mod sync;
+pub mod tz;
+
+#[cfg(target_arch = "xtensa")]
+pub use clock::{BUILD_TIME_US, CLOCK, Clock};
+pub use datetime::{DateTime, parse_http_date};
+pub use tz::TimeZone;
@@ -0,0 +1,303 @@
+use crate::config::{self, CONFIG};
+use crate::net::{SharedNetClientFactory, SharedTcpClient, http_time, ntp};
+use crate::time::CLOCK;
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::Watch;
+use embassy_time::{Duration, Instant, Timer};
+use log::{info, warn};
+use static_cell::StaticCell;
+
+/// Offsets larger than this are corrected at once instead of slewed. Samples
+/// less precise than this are not used to measure drift.
+const STEP_THRESHOLD_US: i64 = 128_000;
+/// Maximum rate at which an offset is slewed in, as in ntpd.
+const MAX_SLEW_PPM: i64 = 500;
+/// Drift estimates beyond this are measurement errors rather than clock
+/// drift. The RTC slow clock can be off by much more than a crystal.
+const MAX_DRIFT_PPM: f32 = 20_000.0;
+/// Weight of a new drift measurement in the running estimate.
+const DRIFT_SMOOTHING: f32 = 0.25;
+/// How often slew and drift corrections are applied between syncs.
+const CORRECTION_INTERVAL: Duration = Duration::from_secs(1);
+/// Delay between failed sync rounds until the first sync. Later failures
+/// back off exponentially up to the resync interval.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_BACKOFF_SHIFT: u32 = 10;
+
+/// Maximum number of tasks that can wait on [`SYNC_STATE`] at once.
+pub const SYNC_SUBSCRIBERS: usize = 4;
+
+/// The latest [`SyncState`], published after every sync round.
+pub static SYNC_STATE: Watch<CriticalSectionRawMutex, SyncState, SYNC_SUBSCRIBERS> =
+    Watch::new_with(SyncState::PENDING);
+
+static TCP_CLIENT: StaticCell<SharedTcpClient> = StaticCell::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSource {
+    Ntp,
+    /// The `Date` header of `net.http_time.url`, used when NTP fails.
+    HttpDate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncStatus {
+    /// The first sync has not finished yet.
+    Pending,
+    /// The last sync succeeded.
+    Synchronised,
+    /// The RTC has been set, but the last resync failed.
+    Stale,
+    /// The first sync gave up after `net.ntp.max_retries`. The RTC is not
+    /// set, resyncs continue in the background.
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncState {
+    pub status: SyncStatus,
+    /// UTC time of the last successful sync, in microseconds since the epoch.
+    pub last_sync_us: Option<u64>,
+    /// Where the last successful sync got the time from.
+    pub source: Option<TimeSource>,
+    /// Source time minus RTC time, measured at the last successful sync.
+    pub last_offset_us: i64,
+    /// Estimated RTC frequency error. Positive when the RTC runs slow.
+    /// Needs two successful syncs.
+    pub drift_ppm: Option<f32>,
+}
+
+impl SyncState {
+    const PENDING: Self = Self {
+        status: SyncStatus::Pending,
+        last_sync_us: None,
+        source: None,
+        last_offset_us: 0,
+        drift_ppm: None,
+    };
+}
+
+/// Returns the latest sync state.
+pub fn sync_state() -> SyncState {
+    SYNC_STATE.try_get().unwrap_or(SyncState::PENDING)
+}
+
+/// Waits until the first sync has either set the RTC or given up.
+pub async fn wait_initial_sync() -> SyncState {
+    wait_for(|state| state.status != SyncStatus::Pending).await
+}
+
+/// Waits until the sync state satisfies `done`.
+pub(crate) async fn wait_for(done: impl Fn(&SyncState) -> bool) -> SyncState {
+    match SYNC_STATE.receiver() {
+        Some(mut receiver) => receiver.get_and(done).await,
+        None => loop {
+            let state = sync_state();
+            if done(&state) {
+                break state;
+            }
+            Timer::after(CORRECTION_INTERVAL).await;
+        },
+    }
+}
+
+/// A reading from one of the time sources.
+struct TimeSample {
+    utc_us: u64,
+    /// How far off `utc_us` can be. Zero for NTP, whose samples are already
+    /// filtered by round trip.
+    uncertainty_us: u64,
+    source: TimeSource,
+}
+
+/// Disciplines the RTC: steps or slews it to each time sample and corrects
+/// the measured drift in between.
+struct Discipline {
+    state: SyncState,
+    /// Offset still to be slewed in.
+    pending_us: i64,
+    /// Offset left uncorrected right after the last sync.
+    baseline_us: i64,
+    /// Corrections applied to the RTC since the last sync.
+    applied_us: i64,
+    /// Fraction of a microsecond of drift correction carried to the next
+    /// correction.
+    carry_us: f32,
+    /// Uptime at the last successful sync.
+    synced_at: Option<Instant>,
+    /// Whether the last correction came from a sample precise enough to
+    /// measure drift against.
+    precise_baseline: bool,
+}
+
+impl Discipline {
+    fn new() -> Self {
+        Self {
+            state: SyncState::PENDING,
+            pending_us: 0,
+            baseline_us: 0,
+            applied_us: 0,
+            carry_us: 0.0,
+            synced_at: None,
+            precise_baseline: false,
+        }
+    }
+
+    fn on_sample(&mut self, sample: TimeSample) {
+        let now = Instant::now();
+        let offset_us = sample.utc_us as i64 - CLOCK.rtc_us() as i64;
+        self.state.status = SyncStatus::Synchronised;
+        self.state.last_sync_us = Some(sample.utc_us);
+        self.state.source = Some(sample.source);
+        self.state.last_offset_us = offset_us;
+
+        if self.synced_at.is_some() && offset_us.unsigned_abs() <= sample.uncertainty_us {
+            info!(
+                "RTC is within {} us of {:?}",
+                sample.uncertainty_us, sample.source
+            );
+            return;
+        }
+        let precise = sample.uncertainty_us < STEP_THRESHOLD_US as u64;
+        if let Some(synced_at) = self.synced_at.filter(|_| precise && self.precise_baseline) {
+            // what the RTC would have drifted without our corrections
+            let error_us = offset_us + self.applied_us - self.baseline_us;
+            let elapsed_us = (now - synced_at).as_micros() as f32;
+            let measured =
+                (error_us as f32 / elapsed_us * 1_000_000.0).clamp(-MAX_DRIFT_PPM, MAX_DRIFT_PPM);
+            self.state.drift_ppm = Some(match self.state.drift_ppm {
+                Some(drift) => drift + (measured - drift) * DRIFT_SMOOTHING,
+                None => measured,
+            });
+        }
+
+        if self.synced_at.is_none() || offset_us.abs() > STEP_THRESHOLD_US {
+            info!("Stepping RTC by {offset_us} us");
+            CLOCK.set_rtc_us(sample.utc_us);
+            self.pending_us = 0;
+        } else {
+            info!("Slewing RTC by {offset_us} us");
+            self.pending_us = offset_us;
+        }
+        self.baseline_us = self.pending_us;
+        self.applied_us = 0;
+        self.synced_at = Some(now);
+        self.precise_baseline = precise;
+    }
+
+    /// Applies slew and drift corrections for `elapsed` since the last call.
+    fn correct(&mut self, elapsed: Duration) {
+        let elapsed_us = elapsed.as_micros() as i64;
+        let max_slew_us = MAX_SLEW_PPM * elapsed_us / 1_000_000;
+        let slew_us = self.pending_us.clamp(-max_slew_us, max_slew_us);
+        self.pending_us -= slew_us;
+
+        let drift_us =
+            self.state.drift_ppm.unwrap_or(0.0) * elapsed_us as f32 / 1_000_000.0 + self.carry_us;
+        let whole_drift_us = drift_us as i64;
+        self.carry_us = drift_us - whole_drift_us as f32;
+
+        let correction_us = slew_us + whole_drift_us;
+        if correction_us != 0 {
+            CLOCK.adjust_rtc_us(correction_us);
+            self.applied_us += correction_us;
+        }
+    }
+
+    /// Keeps correcting the RTC until `deadline`.
+    async fn run_until(&mut self, deadline: Instant) {
+        let mut last = Instant::now();
+        while last < deadline {
+            Timer::at(deadline.min(last + CORRECTION_INTERVAL)).await;
+            let now = Instant::now();
+            if self.synced_at.is_some() {
+                self.correct(now - last);
+            }
+            last = now;
+        }
+    }
+}
+
+/// Gets the time from NTP, falling back to the `Date` header of
+/// `net.http_time.url` when NTP fails.
+async fn fetch_time(
+    stack: Stack<'static>,
+    factory: &'static SharedNetClientFactory,
+    tcp_client: &'static SharedTcpClient,
+    ntp: &config::Ntp,
+    http_time: &config::HttpTime,
+) -> Option<TimeSample> {
+    match ntp::get_real_time_using_ntp(stack, ntp).await {
+        Ok(sample) => {
+            return Some(TimeSample {
+                utc_us: sample.utc_now_us(),
+                uncertainty_us: 0,
+                source: TimeSource::Ntp,
+            });
+        }
+        Err(e) => warn!("Failed to get time from NTP due {e:?}"),
+    }
+
+    let url = http_time.url.as_deref()?;
+    match http_time::get_time_using_http(factory, tcp_client, url, http_time).await {
+        Ok(sample) => Some(TimeSample {
+            utc_us: sample.utc_now_us(),
+            uncertainty_us: sample.uncertainty_us(),
+            source: TimeSource::HttpDate,
+        }),
+        Err(e) => {
+            warn!("Failed to get time from {url} due {e:?}");
+            None
+        }
+    }
+}
+
+/// Syncs the RTC at boot and every `net.ntp.resync_interval_secs` after
+/// that, publishing the result to [`SYNC_STATE`].
+///
+/// NTP is tried first, then `net.http_time` if configured. Call once, and
+/// only after handing the RTC to [`CLOCK`].
+#[embassy_executor::task]
+pub async fn time_sync_task(stack: Stack<'static>, factory: &'static SharedNetClientFactory) {
+    let tcp_client = TCP_CLIENT.init(factory.new_tcp_client());
+    let mut discipline = Discipline::new();
+    let mut failures = 0;
+    loop {
+        let (ntp, http_time) = {
+            let config = CONFIG.lock().await;
+            (config.net.ntp.clone(), config.net.http_time.clone())
+        };
+        let resync_interval = Duration::from_secs(ntp.resync_interval_secs);
+        let wait = match fetch_time(stack, factory, tcp_client, &ntp, &http_time).await {
+            Some(sample) => {
+                failures = 0;
+                discipline.on_sample(sample);
+                if let Some(drift) = discipline.state.drift_ppm {
+                    info!("Estimated RTC drift: {drift:.1} ppm");
+                }
+                resync_interval
+            }
+            None => {
+                failures += 1;
+                let exhausted = ntp.max_retries.is_some_and(|max| failures > max);
+                match discipline.state.status {
+                    SyncStatus::Pending if exhausted => {
+                        warn!("Giving up on time sync after {failures} attempts. Time is not set");
+                        discipline.state.status = SyncStatus::Failed;
+                    }
+                    SyncStatus::Synchronised => discipline.state.status = SyncStatus::Stale,
+                    _ => (),
+                }
+                match discipline.state.status {
+                    SyncStatus::Pending => RETRY_INTERVAL,
+                    // back off, so a blocked network is not hammered
+                    _ => (RETRY_INTERVAL * (1 << failures.min(MAX_BACKOFF_SHIFT)))
+                        .min(resync_interval),
+                }
+            }
+        };
+        SYNC_STATE.sender().send(discipline.state);
+        discipline.run_until(Instant::now() + wait).await;
+    }
+}
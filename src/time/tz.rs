@@ -0,0 +1,661 @@
+use super::DateTime;
+use super::datetime::{MICROS_PER_SECOND, civil_from_days, days_from_civil};
+use alloc::string::{String, ToString};
+use core::fmt;
+use core::str::FromStr;
+
+const MICROS: i64 = MICROS_PER_SECOND as i64;
+const SECONDS_PER_DAY: i64 = super::datetime::SECONDS_PER_DAY as i64;
+
+/// Abbreviations shorter than this are rejected (POSIX.1, section 8.3).
+const NAME_MIN_LEN: usize = 3;
+/// UTC offsets are at most 24 hours.
+const OFFSET_MAX_HOURS: u32 = 24;
+/// Transition times may exceed a day, e.g. `J365/25` (RFC 8536, section 3.3.1).
+const TRANSITION_MAX_HOURS: u32 = 167;
+/// Transitions happen at 02:00 local time unless the rule says otherwise.
+const DEFAULT_TRANSITION_SECS: i32 = 2 * 3600;
+/// DST is one hour ahead of standard time unless the rule says otherwise.
+const DEFAULT_DST_SHIFT_SECS: i32 = 3600;
+/// Rules used when a DST zone has none, the same as glibc (US rules).
+const DEFAULT_DST_START: Transition = Transition {
+    date: RuleDate::MonthWeekDay {
+        month: 3,
+        week: 2,
+        weekday: 0,
+    },
+    time_secs: DEFAULT_TRANSITION_SECS,
+};
+const DEFAULT_DST_END: Transition = Transition {
+    date: RuleDate::MonthWeekDay {
+        month: 11,
+        week: 1,
+        weekday: 0,
+    },
+    time_secs: DEFAULT_TRANSITION_SECS,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum ParseError {
+    #[error("invalid zone abbreviation")]
+    Name,
+    #[error("invalid UTC offset")]
+    Offset,
+    #[error("invalid DST rule")]
+    Rule,
+    #[error("unexpected trailing characters")]
+    TrailingCharacters,
+}
+
+/// A time zone described by a POSIX TZ string, e.g.
+/// `CET-1CEST,M3.5.0,M10.5.0/3` for central Europe.
+///
+/// POSIX offsets count hours west of UTC, so `CET-1` is one hour ahead.
+/// Offsets here are the usual seconds east of UTC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeZone {
+    std: Zone,
+    dst: Option<Dst>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Zone {
+    name: String,
+    utc_offset_secs: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Dst {
+    zone: Zone,
+    /// In standard local time.
+    start: Transition,
+    /// In daylight saving local time.
+    end: Transition,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Transition {
+    date: RuleDate,
+    /// Seconds after local midnight, possibly negative or beyond a day.
+    time_secs: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RuleDate {
+    /// `Jn`: day 1 to 365, never counting February 29.
+    Julian(u16),
+    /// `n`: day 0 to 365, counting February 29 in leap years.
+    ZeroBased(u16),
+    /// `Mm.w.d`: weekday `d` (0 is Sunday) of week `w` (5 is the last) of
+    /// month `m`.
+    MonthWeekDay { month: u8, week: u8, weekday: u8 },
+}
+
+/// A local date and time with the zone it is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalDateTime<'a> {
+    pub datetime: DateTime,
+    /// Seconds east of UTC.
+    pub utc_offset_secs: i32,
+    pub abbreviation: &'a str,
+    pub is_dst: bool,
+}
+
+/// Formats as ISO 8601 with the UTC offset, e.g.
+/// `2025-03-30T03:00:00.000000+02:00`.
+impl fmt::Display for LocalDateTime<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dt = &self.datetime;
+        let sign = if self.utc_offset_secs < 0 { '-' } else { '+' };
+        let offset_mins = self.utc_offset_secs.unsigned_abs() / 60;
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}{sign}{:02}:{:02}",
+            dt.year,
+            dt.month,
+            dt.day,
+            dt.hour,
+            dt.minute,
+            dt.second,
+            dt.micros,
+            offset_mins / 60,
+            offset_mins % 60
+        )
+    }
+}
+
+/// The UTC instants, in microseconds since the Unix epoch, that a local time
+/// maps to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalResult {
+    Single(u64),
+    /// The local time occurs twice when the clocks go back.
+    Ambiguous {
+        earlier: u64,
+        later: u64,
+    },
+    /// The local time is skipped when the clocks go forward. `shifted` reads
+    /// it with the offset from before the gap, which lands as far after the
+    /// gap as the local time was into it, like `mktime` does.
+    Missing {
+        shifted: u64,
+    },
+}
+
+impl LocalResult {
+    /// The only instant, or the earlier one when ambiguous. `None` for a
+    /// missing local time.
+    pub fn earliest(self) -> Option<u64> {
+        match self {
+            LocalResult::Single(us) | LocalResult::Ambiguous { earlier: us, .. } => Some(us),
+            LocalResult::Missing { .. } => None,
+        }
+    }
+}
+
+impl TimeZone {
+    /// UTC without DST.
+    pub fn utc() -> Self {
+        Self {
+            std: Zone {
+                name: "UTC".to_string(),
+                utc_offset_secs: 0,
+            },
+            dst: None,
+        }
+    }
+
+    /// The offset from UTC in effect at `utc_us`, in seconds east of UTC.
+    pub fn utc_offset_secs(&self, utc_us: u64) -> i32 {
+        self.zone_at(utc_us as i64).0.utc_offset_secs
+    }
+
+    /// Converts `utc_us`, in microseconds since the Unix epoch, to local time.
+    pub fn to_local(&self, utc_us: u64) -> LocalDateTime<'_> {
+        let (zone, is_dst) = self.zone_at(utc_us as i64);
+        let local_us = utc_us.saturating_add_signed(zone.utc_offset_secs as i64 * MICROS);
+        LocalDateTime {
+            datetime: DateTime::from_unix_micros(local_us),
+            utc_offset_secs: zone.utc_offset_secs,
+            abbreviation: &zone.name,
+            is_dst,
+        }
+    }
+
+    /// Converts a local time to UTC. Around DST transitions a local time can
+    /// occur twice or not at all.
+    pub fn from_local(&self, local: &DateTime) -> LocalResult {
+        let local_us = local.to_unix_micros() as i64;
+        let to_utc = |zone: &Zone| (local_us - zone.utc_offset_secs as i64 * MICROS).max(0);
+        let Some(dst) = &self.dst else {
+            return LocalResult::Single(to_utc(&self.std) as u64);
+        };
+
+        let as_std = to_utc(&self.std);
+        let as_dst = to_utc(&dst.zone);
+        let std_valid = !self.zone_at(as_std).1;
+        let dst_valid = self.zone_at(as_dst).1;
+        match (std_valid, dst_valid) {
+            (true, true) if as_std != as_dst => LocalResult::Ambiguous {
+                earlier: as_std.min(as_dst) as u64,
+                later: as_std.max(as_dst) as u64,
+            },
+            (true, _) => LocalResult::Single(as_std as u64),
+            (false, true) => LocalResult::Single(as_dst as u64),
+            // the offset before the gap is the smaller one, which gives the
+            // later instant
+            (false, false) => LocalResult::Missing {
+                shifted: as_std.max(as_dst) as u64,
+            },
+        }
+    }
+
+    /// The zone in effect at `utc_us`, and whether it is DST.
+    fn zone_at(&self, utc_us: i64) -> (&Zone, bool) {
+        let Some(dst) = &self.dst else {
+            return (&self.std, false);
+        };
+        let utc_secs = utc_us.div_euclid(MICROS);
+        let local_days = (utc_secs + self.std.utc_offset_secs as i64).div_euclid(SECONDS_PER_DAY);
+        let (year, _, _) = civil_from_days(local_days);
+
+        let start = dst.start.local_secs(year) - self.std.utc_offset_secs as i64;
+        let end = dst.end.local_secs(year) - dst.zone.utc_offset_secs as i64;
+        let in_dst = if start < end {
+            start <= utc_secs && utc_secs < end
+        } else {
+            // southern hemisphere: DST spans the turn of the year
+            utc_secs < end || start <= utc_secs
+        };
+        if in_dst {
+            (&dst.zone, true)
+        } else {
+            (&self.std, false)
+        }
+    }
+}
+
+impl Transition {
+    /// Seconds since the Unix epoch, in local time, at which the transition
+    /// happens in `year`.
+    fn local_secs(&self, year: i64) -> i64 {
+        self.date.days(year) * SECONDS_PER_DAY + self.time_secs as i64
+    }
+}
+
+impl RuleDate {
+    /// Days since the Unix epoch.
+    fn days(&self, year: i64) -> i64 {
+        let jan_1 = days_from_civil(year, 1, 1);
+        match *self {
+            RuleDate::Julian(day) => {
+                let leap_day = (is_leap_year(year) && day >= 60) as i64;
+                jan_1 + day as i64 - 1 + leap_day
+            }
+            RuleDate::ZeroBased(day) => jan_1 + day as i64,
+            RuleDate::MonthWeekDay {
+                month,
+                week,
+                weekday,
+            } => {
+                let first = days_from_civil(year, month, 1);
+                let next_month = if month == 12 {
+                    days_from_civil(year + 1, 1, 1)
+                } else {
+                    days_from_civil(year, month + 1, 1)
+                };
+                let first_match = first + (weekday as i64 - weekday_of(first)).rem_euclid(7);
+                let day = first_match + (week as i64 - 1) * 7;
+                // week 5 means the last one, which may be the fourth
+                if day >= next_month { day - 7 } else { day }
+            }
+        }
+    }
+}
+
+/// 0 is Sunday. 1970-01-01 was a Thursday.
+fn weekday_of(days: i64) -> i64 {
+    (days + 4).rem_euclid(7)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        Self::utc()
+    }
+}
+
+impl FromStr for TimeZone {
+    type Err = ParseError;
+
+    /// Parses `std offset [dst [offset] [,start[/time],end[/time]]]`.
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser { rest: s };
+        let std = Zone {
+            name: parser.name()?,
+            utc_offset_secs: -parser.offset(OFFSET_MAX_HOURS)?,
+        };
+        if parser.rest.is_empty() {
+            return Ok(Self { std, dst: None });
+        }
+
+        let name = parser.name()?;
+        let utc_offset_secs = if parser.rest.is_empty() || parser.rest.starts_with(',') {
+            std.utc_offset_secs + DEFAULT_DST_SHIFT_SECS
+        } else {
+            -parser.offset(OFFSET_MAX_HOURS)?
+        };
+        let (start, end) = if parser.eat(',') {
+            let start = parser.transition()?;
+            if !parser.eat(',') {
+                return Err(ParseError::Rule);
+            }
+            (start, parser.transition()?)
+        } else {
+            (DEFAULT_DST_START, DEFAULT_DST_END)
+        };
+        if !parser.rest.is_empty() {
+            return Err(ParseError::TrailingCharacters);
+        }
+        Ok(Self {
+            std,
+            dst: Some(Dst {
+                zone: Zone {
+                    name,
+                    utc_offset_secs,
+                },
+                start,
+                end,
+            }),
+        })
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn eat(&mut self, c: char) -> bool {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// An alphabetic abbreviation like `CET`, or a quoted one like `<+0530>`.
+    fn name(&mut self) -> Result<String, ParseError> {
+        let name = if self.eat('<') {
+            let end = self.rest.find('>').ok_or(ParseError::Name)?;
+            let name = &self.rest[..end];
+            self.rest = &self.rest[end + 1..];
+            if !name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'-')
+            {
+                return Err(ParseError::Name);
+            }
+            name
+        } else {
+            let end = self
+                .rest
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(self.rest.len());
+            let name = &self.rest[..end];
+            self.rest = &self.rest[end..];
+            name
+        };
+        if name.len() < NAME_MIN_LEN {
+            return Err(ParseError::Name);
+        }
+        Ok(name.to_string())
+    }
+
+    /// `[+|-]hh[:mm[:ss]]` in seconds, positive west of UTC as written.
+    fn offset(&mut self, max_hours: u32) -> Result<i32, ParseError> {
+        let sign = if self.eat('-') {
+            -1
+        } else {
+            self.eat('+');
+            1
+        };
+        let hours = self.number(3).ok_or(ParseError::Offset)?;
+        let mut secs = hours * 3600;
+        for unit in [60, 1] {
+            if !self.eat(':') {
+                break;
+            }
+            let part = self.number(2).filter(|&n| n < 60);
+            secs += unit * part.ok_or(ParseError::Offset)?;
+        }
+        if hours > max_hours {
+            return Err(ParseError::Offset);
+        }
+        Ok(sign * secs as i32)
+    }
+
+    /// `date[/time]`.
+    fn transition(&mut self) -> Result<Transition, ParseError> {
+        let date = if self.eat('J') {
+            let day = self.number(3).filter(|d| (1..=365).contains(d));
+            RuleDate::Julian(day.ok_or(ParseError::Rule)? as u16)
+        } else if self.eat('M') {
+            let month = self.number(2).filter(|m| (1..=12).contains(m));
+            let week = self.eat('.').then(|| self.number(1)).flatten();
+            let week = week.filter(|w| (1..=5).contains(w));
+            let weekday = self.eat('.').then(|| self.number(1)).flatten();
+            let weekday = weekday.filter(|&d| d <= 6);
+            match (month, week, weekday) {
+                (Some(month), Some(week), Some(weekday)) => RuleDate::MonthWeekDay {
+                    month: month as u8,
+                    week: week as u8,
+                    weekday: weekday as u8,
+                },
+                _ => return Err(ParseError::Rule),
+            }
+        } else {
+            let day = self.number(3).filter(|&d| d <= 365);
+            RuleDate::ZeroBased(day.ok_or(ParseError::Rule)? as u16)
+        };
+        let time_secs = if self.eat('/') {
+            self.offset(TRANSITION_MAX_HOURS)
+                .map_err(|_| ParseError::Rule)?
+        } else {
+            DEFAULT_TRANSITION_SECS
+        };
+        Ok(Transition { date, time_secs })
+    }
+
+    /// Up to `max_digits` decimal digits.
+    fn number(&mut self, max_digits: usize) -> Option<u32> {
+        let len = self
+            .rest
+            .bytes()
+            .take(max_digits)
+            .take_while(u8::is_ascii_digit)
+            .count();
+        if len == 0 {
+            return None;
+        }
+        let (digits, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        digits.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CET: &str = "CET-1CEST,M3.5.0,M10.5.0/3";
+    /// Sydney, where DST spans the turn of the year.
+    const AEST: &str = "AEST-10AEDT,M10.1.0,M4.1.0/3";
+
+    fn datetime(year: u16, month: u8, day: u8, hour: u8, minute: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second: 0,
+            micros: 0,
+        }
+    }
+
+    fn utc(year: u16, month: u8, day: u8, hour: u8, minute: u8) -> u64 {
+        datetime(year, month, day, hour, minute).to_unix_micros()
+    }
+
+    fn zone(tz: &str) -> TimeZone {
+        tz.parse().unwrap()
+    }
+
+    #[test]
+    fn central_europe_switches_on_the_last_sundays() {
+        let tz = zone(CET);
+        let second = MICROS as u64;
+
+        let before = tz.to_local(utc(2025, 3, 30, 1, 0) - second);
+        assert_eq!(before.to_string(), "2025-03-30T01:59:59.000000+01:00");
+        assert_eq!((before.abbreviation, before.is_dst), ("CET", false));
+        let after = tz.to_local(utc(2025, 3, 30, 1, 0));
+        assert_eq!(after.to_string(), "2025-03-30T03:00:00.000000+02:00");
+        assert_eq!((after.abbreviation, after.is_dst), ("CEST", true));
+
+        let before = tz.to_local(utc(2025, 10, 26, 1, 0) - second);
+        assert_eq!(before.to_string(), "2025-10-26T02:59:59.000000+02:00");
+        let after = tz.to_local(utc(2025, 10, 26, 1, 0));
+        assert_eq!(after.to_string(), "2025-10-26T02:00:00.000000+01:00");
+        assert!(!after.is_dst);
+
+        assert_eq!(
+            tz.from_local(&datetime(2025, 7, 1, 12, 0)),
+            LocalResult::Single(utc(2025, 7, 1, 10, 0))
+        );
+    }
+
+    #[test]
+    fn central_europe_skips_and_repeats_an_hour() {
+        let tz = zone(CET);
+        let from_local =
+            |month, day, hour, minute| tz.from_local(&datetime(2025, month, day, hour, minute));
+
+        assert_eq!(
+            from_local(3, 30, 1, 59),
+            LocalResult::Single(utc(2025, 3, 30, 0, 59))
+        );
+        for (hour, minute) in [(2, 0), (2, 30), (2, 59)] {
+            assert_eq!(
+                from_local(3, 30, hour, minute),
+                LocalResult::Missing {
+                    shifted: utc(2025, 3, 30, hour - 1, minute)
+                }
+            );
+        }
+        assert_eq!(from_local(3, 30, 2, 30).earliest(), None);
+        assert_eq!(
+            from_local(3, 30, 3, 0),
+            LocalResult::Single(utc(2025, 3, 30, 1, 0))
+        );
+
+        assert_eq!(
+            from_local(10, 26, 1, 59),
+            LocalResult::Single(utc(2025, 10, 25, 23, 59))
+        );
+        for (hour, minute) in [(2, 0), (2, 30), (2, 59)] {
+            assert_eq!(
+                from_local(10, 26, hour, minute),
+                LocalResult::Ambiguous {
+                    earlier: utc(2025, 10, 26, hour - 2, minute),
+                    later: utc(2025, 10, 26, hour - 1, minute),
+                }
+            );
+        }
+        assert_eq!(
+            from_local(10, 26, 3, 0),
+            LocalResult::Single(utc(2025, 10, 26, 2, 0))
+        );
+    }
+
+    #[test]
+    fn southern_hemisphere_dst_spans_the_new_year() {
+        let tz = zone(AEST);
+        assert_eq!(tz.utc_offset_secs(utc(2025, 1, 15, 12, 0)), 11 * 3600);
+        assert_eq!(tz.utc_offset_secs(utc(2025, 7, 1, 12, 0)), 10 * 3600);
+        assert_eq!(tz.utc_offset_secs(utc(2025, 12, 31, 23, 0)), 11 * 3600);
+
+        // DST ends at 03:00 AEDT on the first Sunday of April
+        let second = MICROS as u64;
+        assert!(tz.to_local(utc(2025, 4, 5, 16, 0) - second).is_dst);
+        let after = tz.to_local(utc(2025, 4, 5, 16, 0));
+        assert_eq!(after.to_string(), "2025-04-06T02:00:00.000000+10:00");
+        assert_eq!(
+            tz.from_local(&datetime(2025, 4, 6, 2, 30)),
+            LocalResult::Ambiguous {
+                earlier: utc(2025, 4, 5, 15, 30),
+                later: utc(2025, 4, 5, 16, 30),
+            }
+        );
+
+        // and starts at 02:00 AEST on the first Sunday of October
+        let after = tz.to_local(utc(2025, 10, 4, 16, 0));
+        assert_eq!(after.to_string(), "2025-10-05T03:00:00.000000+11:00");
+        assert_eq!(
+            tz.from_local(&datetime(2025, 10, 5, 2, 30)),
+            LocalResult::Missing {
+                shifted: utc(2025, 10, 4, 16, 30)
+            }
+        );
+    }
+
+    #[test]
+    fn julian_days_skip_february_29_and_zero_based_days_count_it() {
+        let tz = zone("AAA0BBB,J60/0,59/0");
+        let dst = tz.dst.as_ref().unwrap();
+        assert_eq!(dst.start.date, RuleDate::Julian(60));
+        assert_eq!(dst.end.date, RuleDate::ZeroBased(59));
+        assert_eq!(dst.start.time_secs, 0);
+
+        for year in [2023, 2024] {
+            assert_eq!(RuleDate::Julian(60).days(year), days_from_civil(year, 3, 1));
+        }
+        assert_eq!(
+            RuleDate::ZeroBased(59).days(2024),
+            days_from_civil(2024, 2, 29)
+        );
+        assert_eq!(
+            RuleDate::ZeroBased(59).days(2023),
+            days_from_civil(2023, 3, 1)
+        );
+        assert_eq!(
+            RuleDate::Julian(365).days(2024),
+            days_from_civil(2024, 12, 31)
+        );
+        assert_eq!(
+            RuleDate::ZeroBased(365).days(2024),
+            days_from_civil(2024, 12, 31)
+        );
+
+        // DST from March 1 to February 29 of the next leap year
+        assert_eq!(tz.utc_offset_secs(utc(2023, 3, 1, 0, 0)), 3600);
+        assert_eq!(tz.utc_offset_secs(utc(2024, 2, 28, 23, 0)), 0);
+    }
+
+    #[test]
+    fn fixed_offsets_have_no_transitions() {
+        let tz = zone("<+0530>-5:30");
+        let local = tz.to_local(utc(2025, 3, 30, 1, 30));
+        assert_eq!(local.to_string(), "2025-03-30T07:00:00.000000+05:30");
+        assert_eq!((local.abbreviation, local.is_dst), ("+0530", false));
+        assert_eq!(
+            tz.from_local(&datetime(2025, 3, 30, 7, 0)),
+            LocalResult::Single(utc(2025, 3, 30, 1, 30))
+        );
+
+        assert_eq!(zone("EST5").utc_offset_secs(0), -5 * 3600);
+        assert_eq!(zone("UTC0"), TimeZone::utc());
+    }
+
+    #[test]
+    fn dst_without_rules_uses_the_us_rules() {
+        let tz = zone("EST5EDT");
+        // 02:00 EST on the second Sunday of March
+        assert_eq!(tz.utc_offset_secs(utc(2025, 3, 9, 7, 0) - 1), -5 * 3600);
+        assert_eq!(tz.utc_offset_secs(utc(2025, 3, 9, 7, 0)), -4 * 3600);
+        // 02:00 EDT on the first Sunday of November
+        assert_eq!(tz.utc_offset_secs(utc(2025, 11, 2, 6, 0)), -5 * 3600);
+    }
+
+    #[test]
+    fn invalid_strings_are_rejected() {
+        let cases = [
+            ("", ParseError::Name),
+            ("CE-1", ParseError::Name),
+            ("<+05", ParseError::Name),
+            ("CET", ParseError::Offset),
+            ("CET-25", ParseError::Offset),
+            ("CET-1:60", ParseError::Offset),
+            ("CET-1CEST,M3.5.0", ParseError::Rule),
+            ("CET-1CEST,M13.5.0,M10.5.0", ParseError::Rule),
+            ("CET-1CEST,M3.6.0,M10.5.0", ParseError::Rule),
+            ("CET-1CEST,M3.5.7,M10.5.0", ParseError::Rule),
+            ("AAA0BBB,J0,J300", ParseError::Rule),
+            ("AAA0BBB,J60,366", ParseError::Rule),
+            ("CET-1CEST,M3.5.0,M10.5.0/168", ParseError::Rule),
+            (
+                "CET-1CEST,M3.5.0,M10.5.0/3x",
+                ParseError::TrailingCharacters,
+            ),
+        ];
+        for (tz, error) in cases {
+            assert_eq!(tz.parse::<TimeZone>(), Err(error), "{tz:?}");
+        }
+    }
+}
@@ -1,22 +1,35 @@
 extern crate alloc;
 
+use crate::config::{self, CONFIG, ConfigReceiver, Network};
 use alloc::borrow::ToOwned;
+use alloc::vec::Vec;
+use core::fmt;
 use embassy_executor::Spawner;
-use embassy_net::{Config, DhcpConfig, Runner, Stack, StackResources};
+use embassy_futures::select::{Either, select};
+use embassy_net::{
+    Config, ConfigV6, DhcpConfig, Ipv4Cidr, Ipv6Cidr, Runner, Stack, StackResources,
+    StaticConfigV4, StaticConfigV6,
+};
 use embassy_time::{Duration, Timer};
 use esp_hal::{peripherals, rng::Rng, timer::timg::TimerGroup};
 use esp_wifi::wifi::{
-    ClientConfiguration, Configuration, Interfaces, WifiController, WifiDevice, WifiEvent, WifiMode,
+    AccessPointInfo, ClientConfiguration, Configuration, Interfaces, WifiController, WifiDevice,
+    WifiEvent, WifiMode,
 };
-use log::info;
+use log::{info, warn};
 use rand_core::RngCore;
 use static_cell::StaticCell;
 
 static WIFI_INIT: StaticCell<esp_wifi::EspWifiController<'static>> = StaticCell::new();
-static NET_RESOURCES: StaticCell<StackResources<4>> = StaticCell::new();
+static NET_RESOURCES: StaticCell<StackResources<5>> = StaticCell::new();
 static NET_STACK: StaticCell<Stack<'static>> = StaticCell::new();
 static NET_RUNNER: StaticCell<Runner<'static, WifiDevice>> = StaticCell::new();
 
+/// Maximum number of access points considered per scan.
+const MAX_SCAN_RESULTS: usize = 20;
+/// Consecutive connect failures after which the next network is tried.
+const MAX_CONNECT_ATTEMPTS: u32 = 3;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("WifiInitError")]
@@ -64,8 +77,9 @@ pub async fn init_stack<'a>(
     mut rng: Rng,
 ) -> Result<Stack<'a>, Error> {
     // Setup embassy-net stack
-    let config = Config::dhcpv4(DhcpConfig::default());
-    let resources = NET_RESOURCES.init(StackResources::<4>::new());
+    let net = CONFIG.lock().await.net.clone();
+    let config = stack_config(&net);
+    let resources = NET_RESOURCES.init(StackResources::<5>::new());
     let (stack, runner) = embassy_net::new(sta, config, resources, rng.next_u64());
     let stack = NET_STACK.init(stack).to_owned();
     let runner = NET_RUNNER.init(runner);
@@ -74,46 +88,252 @@ pub async fn init_stack<'a>(
     spawner
         .spawn(net_task(runner))
         .map_err(|_| Error::NetTaskError)?;
+    if net.ipv6 == config::Ipv6::Slaac {
+        spawner
+            .spawn(crate::net::slaac::slaac_task(stack))
+            .map_err(|_| Error::NetTaskError)?;
+    }
     stack.wait_config_up().await;
     Ok(stack)
 }
 
+/// Maps the `[net.ipv4]` and `[net.ipv6]` config sections onto the
+/// embassy-net stack config.
+///
+/// With SLAAC, IPv6 starts unconfigured and is set up by
+/// [`crate::net::slaac::slaac_task`] once a router advertisement arrives.
+fn stack_config(net: &config::Net) -> Config {
+    let mut config = match &net.ipv4 {
+        config::Ipv4::Dhcp(dhcp) => {
+            let mut config = DhcpConfig::default();
+            if let Some(hostname) = &dhcp.hostname {
+                // validated to fit, see `config::Dhcp::validate`
+                config.hostname = heapless::String::try_from(hostname.as_str()).ok();
+            }
+            if let Some(secs) = dhcp.max_lease_secs {
+                config.max_lease_duration = Some(Duration::from_secs(secs));
+            }
+            if let Some(secs) = dhcp.discover_timeout_secs {
+                config.retry_config.discover_timeout = smoltcp::time::Duration::from_secs(secs);
+            }
+            if let Some(secs) = dhcp.request_timeout_secs {
+                config.retry_config.initial_request_timeout =
+                    smoltcp::time::Duration::from_secs(secs);
+            }
+            if let Some(retries) = dhcp.request_retries {
+                config.retry_config.request_retries = retries;
+            }
+            info!("Using DHCP for IPv4");
+            Config::dhcpv4(config)
+        }
+        config::Ipv4::Static(ipv4) => {
+            info!(
+                "Using static IPv4 address {}/{}",
+                ipv4.address, ipv4.prefix_len
+            );
+            Config::ipv4_static(StaticConfigV4 {
+                address: Ipv4Cidr::new(ipv4.address, ipv4.prefix_len),
+                gateway: ipv4.gateway,
+                // validated to fit, see `config::StaticIpv4::validate`
+                dns_servers: ipv4.dns_servers.iter().copied().take(3).collect(),
+            })
+        }
+    };
+    config.ipv6 = match &net.ipv6 {
+        config::Ipv6::Disabled | config::Ipv6::Slaac => ConfigV6::None,
+        config::Ipv6::Static(ipv6) => {
+            info!(
+                "Using static IPv6 address {}/{}",
+                ipv6.address, ipv6.prefix_len
+            );
+            ConfigV6::Static(StaticConfigV6 {
+                address: Ipv6Cidr::new(ipv6.address, ipv6.prefix_len),
+                gateway: ipv6.gateway,
+                // validated to fit, see `config::StaticIpv6::validate`
+                dns_servers: ipv6.dns_servers.iter().copied().take(3).collect(),
+            })
+        }
+    };
+    config
+}
+
+/// The addresses the stack currently holds, per address family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpStatus {
+    pub ipv4: Option<Ipv4Cidr>,
+    pub ipv6: Option<Ipv6Cidr>,
+}
+
+impl IpStatus {
+    pub fn of(stack: Stack<'_>) -> Self {
+        Self {
+            ipv4: stack.config_v4().map(|c| c.address),
+            ipv6: stack.config_v6().map(|c| c.address),
+        }
+    }
+
+    pub fn is_dual_stack(&self) -> bool {
+        self.ipv4.is_some() && self.ipv6.is_some()
+    }
+}
+
+impl fmt::Display for IpStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.ipv4, self.ipv6) {
+            (Some(v4), Some(v6)) => write!(f, "dual-stack {v4}, {v6}"),
+            (Some(v4), None) => write!(f, "IPv4-only {v4}"),
+            (None, Some(v6)) => write!(f, "IPv6-only {v6}"),
+            (None, None) => write!(f, "no address"),
+        }
+    }
+}
+
 #[embassy_executor::task]
 pub async fn net_task(runner: &'static mut Runner<'static, WifiDevice<'static>>) {
     runner.run().await;
 }
 
+/// Waits for a config change. Never completes without a receiver.
+async fn config_changed(changes: &mut Option<ConfigReceiver<'static>>) {
+    match changes {
+        Some(changes) => {
+            changes.changed().await;
+        }
+        None => core::future::pending().await,
+    }
+}
+
+/// Picks the visible network with the highest priority, breaking ties by
+/// signal strength. Networks listed in `exhausted` are skipped.
+fn select_network<'a>(
+    networks: &[Network],
+    scan: &'a [AccessPointInfo],
+    exhausted: &[usize],
+) -> Option<(usize, &'a AccessPointInfo)> {
+    networks
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !exhausted.contains(i))
+        .flat_map(|(i, network)| {
+            scan.iter()
+                .filter(move |ap| {
+                    ap.ssid == network.ssid && network.bssid.is_none_or(|b| b.0 == ap.bssid)
+                })
+                .map(move |ap| (i, network.priority, ap))
+        })
+        .max_by_key(|(_, priority, ap)| (*priority, ap.signal_strength))
+        .map(|(i, _, ap)| (i, ap))
+}
+
+/// Tries to connect to the configured network up to `MAX_CONNECT_ATTEMPTS`
+/// times.
+async fn connect(controller: &mut WifiController<'static>, ssid: &str) -> bool {
+    for attempt in 1..=MAX_CONNECT_ATTEMPTS {
+        info!("Connecting to wifi {ssid} (attempt {attempt}/{MAX_CONNECT_ATTEMPTS})...");
+        match controller.connect_async().await {
+            Ok(_) => return true,
+            Err(e) => {
+                info!("Failed to connect to wifi: {e:?}");
+                Timer::after(Duration::from_millis(5000)).await
+            }
+        }
+    }
+    false
+}
+
 #[embassy_executor::task]
 pub async fn wifi_connect(mut controller: WifiController<'static>) {
     info!("Start connection task");
     info!("Device capabilities: {:?}", controller.capabilities());
+    let mut config_changes = CONFIG.subscribe();
+    if config_changes.is_none() {
+        warn!("No config subscriber slot left. Wifi config changes need a restart");
+    }
+    if !matches!(controller.is_started(), Ok(true)) {
+        let client_config = Configuration::Client(ClientConfiguration::default());
+        controller.set_configuration(&client_config).unwrap();
+        info!("Starting wifi...");
+        controller.start_async().await.unwrap();
+        info!("Wifi started!");
+    }
+
+    let mut networks = CONFIG.lock().await.wifi.networks.clone();
+    // networks that failed `MAX_CONNECT_ATTEMPTS` times since the last reset
+    let mut exhausted = Vec::new();
     loop {
-        if esp_wifi::wifi::wifi_state() == esp_wifi::wifi::WifiState::StaConnected {
-            // wait until we're no longer connected
-            controller.wait_for_event(WifiEvent::StaDisconnected).await;
-            info!("Disconnected from wifi");
-            Timer::after(Duration::from_millis(5000)).await
+        let connected = esp_wifi::wifi::wifi_state() == esp_wifi::wifi::WifiState::StaConnected;
+        if connected {
+            // wait until we're no longer connected or the config changed
+            match select(
+                controller.wait_for_event(WifiEvent::StaDisconnected),
+                config_changed(&mut config_changes),
+            )
+            .await
+            {
+                Either::First(_) => {
+                    info!("Disconnected from wifi");
+                    Timer::after(Duration::from_millis(5000)).await
+                }
+                Either::Second(_) => (),
+            }
         }
 
-        if !matches!(controller.is_started(), Ok(true)) {
-            let client_config = Configuration::Client(ClientConfiguration {
-                ssid: crate::config::CONFIG.wifi.ssid.to_owned(),
-                password: crate::config::CONFIG.wifi.password.clone(),
-                channel: crate::config::CONFIG.wifi.channel,
-                ..Default::default()
-            });
-            controller.set_configuration(&client_config).unwrap();
-            info!("Starting wifi...");
-            controller.start_async().await.unwrap();
-            info!("Wifi started!");
+        let current = CONFIG.lock().await.wifi.networks.clone();
+        if current != networks {
+            info!("Wifi config changed. Reconnecting...");
+            networks = current;
+            exhausted.clear();
+            if esp_wifi::wifi::wifi_state() == esp_wifi::wifi::WifiState::StaConnected {
+                if let Err(e) = controller.disconnect_async().await {
+                    warn!("Failed to disconnect from wifi: {e:?}");
+                }
+            }
+        } else if esp_wifi::wifi::wifi_state() == esp_wifi::wifi::WifiState::StaConnected {
+            continue;
         }
-        info!("Connecting to wifi...");
-        match controller.connect_async().await {
-            Ok(_) => info!("Wifi connected!"),
+
+        info!("Scanning for wifi networks...");
+        let scan = match controller.scan_n_async(MAX_SCAN_RESULTS).await {
+            Ok(scan) => scan,
             Err(e) => {
-                info!("Failed to connect to wifi: {e:?}");
-                Timer::after(Duration::from_millis(5000)).await
+                warn!("Failed to scan for wifi networks: {e:?}");
+                Timer::after(Duration::from_millis(5000)).await;
+                continue;
             }
+        };
+        let Some((index, ap)) = select_network(&networks, &scan, &exhausted) else {
+            if exhausted.is_empty() {
+                info!("No configured wifi network in range");
+            } else {
+                info!("All configured wifi networks in range failed. Retrying all of them");
+                exhausted.clear();
+            }
+            Timer::after(Duration::from_millis(5000)).await;
+            continue;
+        };
+
+        let network = &networks[index];
+        info!(
+            "Selected wifi {} (priority {}, rssi {})",
+            network.ssid, network.priority, ap.signal_strength
+        );
+        let client_config = Configuration::Client(ClientConfiguration {
+            ssid: network.ssid.clone(),
+            password: network.password.clone(),
+            bssid: network.bssid.map(|b| b.0),
+            channel: network.channel.or(Some(ap.channel)),
+            ..Default::default()
+        });
+        if let Err(e) = controller.set_configuration(&client_config) {
+            warn!("Failed to configure wifi: {e:?}");
+            exhausted.push(index);
+            continue;
+        }
+        if connect(&mut controller, &network.ssid).await {
+            info!("Wifi connected!");
+        } else {
+            warn!("Giving up on wifi {} for now", network.ssid);
+            exhausted.push(index);
         }
     }
 }
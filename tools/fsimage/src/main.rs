@@ -0,0 +1,224 @@
+//! Builds and inspects littlefs images of the `storage` partition.
+//!
+//! ```text
+//! fsimage pack <dir> <image>      packs the files in <dir> into a new image
+//! fsimage unpack <image> <dir>    extracts the files of <image> into <dir>
+//! fsimage list <image>            lists the files of <image> and its usage
+//! ```
+//!
+//! The geometry is the one the firmware mounts, from
+//! `src/filesystem/geometry.rs`. A packed image is written to the offset of
+//! the `storage` partition in `partitions.csv` with e.g.
+//! `espflash write-bin <offset> storage.bin`, and a partition read back from
+//! a device can be larger than the filesystem.
+
+#[path = "../../../src/filesystem/geometry.rs"]
+mod geometry;
+
+use littlefs2::driver::Storage;
+use littlefs2::fs::{FileType, Filesystem};
+use littlefs2::io::{self, Error, Read};
+use littlefs2::path::{Path as FsPath, PathBuf};
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+const ROOT: &FsPath = littlefs2::path!("/");
+/// Value of erased flash.
+const ERASED: u8 = 0xff;
+
+/// A filesystem image in memory.
+struct Image(Vec<u8>);
+
+impl Image {
+    fn erased() -> Self {
+        Self(vec![ERASED; geometry::FILESYSTEM_SIZE])
+    }
+
+    fn load(path: &Path) -> Result<Self, String> {
+        let mut data =
+            fs::read(path).map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+        if data.len() < geometry::FILESYSTEM_SIZE {
+            return Err(format!(
+                "{} has {} bytes, less than the {} byte filesystem",
+                path.display(),
+                data.len(),
+                geometry::FILESYSTEM_SIZE
+            ));
+        }
+        // the partition can be larger than the filesystem
+        data.truncate(geometry::FILESYSTEM_SIZE);
+        Ok(Self(data))
+    }
+}
+
+impl Storage for Image {
+    const READ_SIZE: usize = geometry::READ_SIZE;
+    const WRITE_SIZE: usize = geometry::WRITE_SIZE;
+    const BLOCK_SIZE: usize = geometry::BLOCK_SIZE;
+    const BLOCK_COUNT: usize = geometry::BLOCK_COUNT;
+
+    type CACHE_SIZE = geometry::CacheSize;
+    type LOOKAHEAD_SIZE = geometry::LookaheadSize;
+
+    fn read(&mut self, off: usize, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.0.get(off..off + buf.len()).ok_or(Error::IO)?;
+        buf.copy_from_slice(data);
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, off: usize, data: &[u8]) -> io::Result<usize> {
+        let target = self.0.get_mut(off..off + data.len()).ok_or(Error::IO)?;
+        target.copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn erase(&mut self, off: usize, len: usize) -> io::Result<usize> {
+        let target = self.0.get_mut(off..off + len).ok_or(Error::IO)?;
+        target.fill(ERASED);
+        Ok(len)
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["pack", dir, image] => pack(Path::new(dir), Path::new(image)),
+        ["unpack", image, dir] => unpack(Path::new(image), Path::new(dir)),
+        ["list", image] => list(Path::new(image)),
+        _ => Err("Usage: fsimage pack <dir> <image> | unpack <image> <dir> | list <image>".into()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn pack(dir: &Path, image_path: &Path) -> Result<(), String> {
+    let mut image = Image::erased();
+    Filesystem::format(&mut image).map_err(|e| fs_error("Cannot format image", e))?;
+    Filesystem::mount_and_then(&mut image, |fs| {
+        copy_in(fs, dir, ROOT)?;
+        report_usage(fs)
+    })
+    .map_err(|e| fs_error("Cannot pack image", e))?;
+    fs::write(image_path, &image.0)
+        .map_err(|e| format!("Cannot write {}: {e}", image_path.display()))
+}
+
+fn unpack(image_path: &Path, dir: &Path) -> Result<(), String> {
+    let mut image = Image::load(image_path)?;
+    fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {e}", dir.display()))?;
+    Filesystem::mount_and_then(&mut image, |fs| copy_out(fs, ROOT, dir))
+        .map_err(|e| fs_error("Cannot unpack image", e))
+}
+
+fn list(image_path: &Path) -> Result<(), String> {
+    let mut image = Image::load(image_path)?;
+    Filesystem::mount_and_then(&mut image, |fs| {
+        walk(fs, ROOT, &mut |path, file_type, len| {
+            match file_type {
+                FileType::Dir => println!("{:>8}  {path}/", "-"),
+                FileType::File => println!("{len:>8}  {path}"),
+            }
+            Ok(())
+        })?;
+        report_usage(fs)
+    })
+    .map_err(|e| fs_error("Cannot list image", e))
+}
+
+/// Copies the host directory `from` into the directory `to` of the image.
+fn copy_in(fs: &Filesystem<Image>, from: &Path, to: &FsPath) -> io::Result<()> {
+    let entries = fs::read_dir(from).map_err(|e| host_error(from, e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| host_error(from, e))?;
+        let host_path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_str().ok_or_else(|| {
+            eprintln!("{} is not valid UTF-8", host_path.display());
+            Error::INVALID
+        })?;
+        let path = to.join(&PathBuf::try_from(name).map_err(|_| Error::FILENAME_TOO_LONG)?);
+        let file_type = entry.file_type().map_err(|e| host_error(&host_path, e))?;
+        if file_type.is_dir() {
+            fs.create_dir(&path)?;
+            copy_in(fs, &host_path, &path)?;
+        } else {
+            let contents = fs::read(&host_path).map_err(|e| host_error(&host_path, e))?;
+            fs.write(&path, &contents)?;
+            println!("{path}: {} bytes", contents.len());
+        }
+    }
+    Ok(())
+}
+
+/// Copies the directory `from` of the image into the host directory `to`.
+fn copy_out(fs: &Filesystem<Image>, from: &FsPath, to: &Path) -> io::Result<()> {
+    walk(fs, from, &mut |path, file_type, _| {
+        let host_path = to.join(path.as_str().trim_start_matches('/'));
+        match file_type {
+            FileType::Dir => fs::create_dir_all(&host_path),
+            FileType::File => {
+                let contents = read(fs, path)?;
+                println!("{path}: {} bytes", contents.len());
+                fs::write(&host_path, contents)
+            }
+        }
+        .map_err(|e| host_error(&host_path, e))
+    })
+}
+
+/// Calls `f` with every entry below `dir`, parents first.
+fn walk(
+    fs: &Filesystem<Image>,
+    dir: &FsPath,
+    f: &mut dyn FnMut(&FsPath, FileType, usize) -> io::Result<()>,
+) -> io::Result<()> {
+    let entries = fs.read_dir_and_then(dir, |entries| {
+        entries
+            .filter(|entry| !matches!(entry, Ok(e) if matches!(e.file_name().as_str(), "." | "..")))
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+    for entry in entries {
+        let path = PathBuf::from(entry.path());
+        f(&path, entry.file_type(), entry.metadata().len())?;
+        if entry.file_type() == FileType::Dir {
+            walk(fs, &path, f)?;
+        }
+    }
+    Ok(())
+}
+
+fn read(fs: &Filesystem<Image>, path: &FsPath) -> io::Result<Vec<u8>> {
+    fs.open_file_and_then(path, |file| {
+        let mut contents = vec![0; file.len()?];
+        file.read_exact(&mut contents)?;
+        Ok(contents)
+    })
+}
+
+fn report_usage(fs: &Filesystem<Image>) -> io::Result<()> {
+    let free = fs.available_blocks()?;
+    let total = fs.total_blocks();
+    println!(
+        "{} of {total} blocks of {} bytes used",
+        total - free,
+        geometry::BLOCK_SIZE
+    );
+    Ok(())
+}
+
+/// Prints a host IO error, which littlefs errors cannot carry.
+fn host_error(path: &Path, e: std::io::Error) -> Error {
+    eprintln!("{}: {e}", path.display());
+    Error::IO
+}
+
+fn fs_error(context: &str, e: Error) -> String {
+    format!("{context}: littlefs error {}", e.code())
+}